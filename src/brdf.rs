@@ -0,0 +1,78 @@
+// Metallic/roughness BRDF: Cook-Torrance with the GGX distribution, Smith-Schlick geometry and Fresnel-Schlick,
+// plus a Lambert diffuse that only receives the energy not reflected by the specular part.
+// All directions are normalized and point away from the surface.
+use std::f64::consts::PI;
+use vecmath::{vec3_add, vec3_dot, vec3_normalized};
+
+// Reflectance of dielectrics at normal incidence
+const DIELECTRIC_F0: f64 = 0.04;
+// Keeps the highlight of perfectly smooth surfaces finite
+const MIN_ROUGHNESS: f64 = 0.03;
+
+pub struct BrdfInput {
+    pub normal: [f64; 3],
+    pub view: [f64; 3],
+    pub light: [f64; 3],
+    pub color: [f64; 3],
+    pub metallic: f64,
+    pub roughness: f64,
+}
+
+fn distribution_ggx(n_dot_h: f64, alpha: f64) -> f64 {
+    let a2 = alpha * alpha;
+    let d = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    a2 / (PI * d * d)
+}
+
+fn geometry_schlick(n_dot_x: f64, k: f64) -> f64 {
+    n_dot_x / (n_dot_x * (1.0 - k) + k)
+}
+
+pub fn fresnel_schlick(cos_theta: f64, f0: [f64; 3]) -> [f64; 3] {
+    let f = (1.0 - cos_theta).clamp(0.0, 1.0).powi(5);
+    [
+        f0[0] + (1.0 - f0[0]) * f,
+        f0[1] + (1.0 - f0[1]) * f,
+        f0[2] + (1.0 - f0[2]) * f,
+    ]
+}
+
+pub fn base_reflectance(color: [f64; 3], metallic: f64) -> [f64; 3] {
+    [
+        DIELECTRIC_F0 + (color[0] - DIELECTRIC_F0) * metallic,
+        DIELECTRIC_F0 + (color[1] - DIELECTRIC_F0) * metallic,
+        DIELECTRIC_F0 + (color[2] - DIELECTRIC_F0) * metallic,
+    ]
+}
+
+// Reflected radiance per unit of incoming light, already multiplied by the cosine of the light angle
+pub fn evaluate(i: &BrdfInput) -> [f64; 3] {
+    let n_dot_l = vec3_dot(i.normal, i.light);
+    let n_dot_v = vec3_dot(i.normal, i.view);
+    if n_dot_l <= 0.0 {
+        return [0.0, 0.0, 0.0];
+    }
+    // Normals from finite differences can face slightly away from the camera at silhouettes
+    let n_dot_v = n_dot_v.max(1e-4);
+
+    let half = vec3_normalized(vec3_add(i.view, i.light));
+    let n_dot_h = vec3_dot(i.normal, half).max(0.0);
+    let v_dot_h = vec3_dot(i.view, half).max(0.0);
+
+    let roughness = i.roughness.clamp(MIN_ROUGHNESS, 1.0);
+    let alpha = roughness * roughness;
+    let k = (roughness + 1.0) * (roughness + 1.0) / 8.0;
+
+    let d = distribution_ggx(n_dot_h, alpha);
+    let g = geometry_schlick(n_dot_v, k) * geometry_schlick(n_dot_l, k);
+    let f = fresnel_schlick(v_dot_h, base_reflectance(i.color, i.metallic));
+    let specular = d * g / (4.0 * n_dot_v * n_dot_l);
+
+    let metallic = i.metallic.clamp(0.0, 1.0);
+    let mut result = [0.0; 3];
+    for c in 0..3 {
+        let k_d = (1.0 - f[c]) * (1.0 - metallic);
+        result[c] = (k_d * i.color[c] / PI + f[c] * specular) * n_dot_l;
+    }
+    result
+}
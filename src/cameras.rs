@@ -6,13 +6,23 @@ use std::{rc::Rc, cell::RefCell};
 pub trait Camera{
     fn generate_ray(&self, x: f64, y: f64, sample: CameraSample) -> crate::Ray;
     fn evaluate(&mut self, t: f64);
+    // State at the end of the current shutter stratum, rays are interpolated towards it by their time sample
+    fn evaluate_end(&mut self, t: f64);
+}
+
+// Blends the rays generated at the start and end of the shutter stratum
+fn interpolate_ray(start: crate::Ray, end: crate::Ray, time: f64) -> crate::Ray{
+    let dir = vecmath::vec3_normalized(helpers::vec_interpolate(start.0, end.0, time));
+    (dir, helpers::vec_interpolate(start.1, end.1, time))
 }
 
 pub struct PinholeCamera{
     pos: [f64!(); 3],
     pos_f64: [f64;3],
+    pos_end: [f64;3],
     rot: [f64!(); 3],
     mat_inv: [[f64;3];3],
+    mat_inv_end: [[f64;3];3],
     ar: f64,
     scale: f64
 }
@@ -26,7 +36,9 @@ impl Camera for PinholeCamera{
         //let rx = (2.0 * (x + 0.5) / configuration::width_f - 1.0) * self.ar * self.scale;
         //let ry = (1.0 - 2.0 * (y + 0.5) / configuration::height_f) * self.scale;
         let dir = vecmath::vec3_normalized([rx, ry, -1.0]);
-        return (helpers::mat_dir_mul3(self.mat_inv, dir), [self.pos_f64[0], self.pos_f64[1],self.pos_f64[2]]);
+        let start = (helpers::mat_dir_mul3(self.mat_inv, dir), [self.pos_f64[0], self.pos_f64[1],self.pos_f64[2]]);
+        let end = (helpers::mat_dir_mul3(self.mat_inv_end, dir), self.pos_end);
+        interpolate_ray(start, end, sample.2)
     }
     fn evaluate(&mut self, t: f64){
         self.pos_f64 = [
@@ -36,6 +48,10 @@ impl Camera for PinholeCamera{
         ];
         self.mat_inv = vecmath::mat3_inv( helpers::mat_rotation3(get_f64v!(self.rot)));
     }
+    fn evaluate_end(&mut self, t: f64){
+        self.pos_end = get_f64v!(self.pos);
+        self.mat_inv_end = vecmath::mat3_inv( helpers::mat_rotation3(get_f64v!(self.rot)));
+    }
 }
 
 impl PinholeCamera{
@@ -52,11 +68,13 @@ impl PinholeCamera{
         ];
         PinholeCamera{
             pos_f64,
+            pos_end: pos_f64,
             pos,
             rot, 
             ar,
             scale,
-            mat_inv: mat
+            mat_inv: mat,
+            mat_inv_end: mat
         }
     }
 }
@@ -72,8 +90,10 @@ pub struct ThinLensCamera{
     focal_distance: f64!(),
     pos: [f64!(); 3],
     pos_f64: [f64;3],
+    pos_end: [f64;3],
     rot: [f64!(); 3],
     mat_inv: [[f64;4];4],
+    mat_inv_end: [[f64;4];4],
     ar: f64,
     scale: f64
 }
@@ -93,7 +113,10 @@ impl Camera for ThinLensCamera{
         
         let focus = [dir[0] * ft, dir[1] * ft, dir[2] * ft];
         // println!("{:?}", p_lens);
-        return (helpers::mat_dir_mul(self.mat_inv,vecmath::vec3_normalized([ray_o[0] - focus[0], ray_o[1] - focus[1], -focus[2]])), [ray_o[0] + self.pos_f64[0], ray_o[0] + self.pos_f64[0], ray_o[1] + self.pos_f64[2]])
+        let local_dir = vecmath::vec3_normalized([ray_o[0] - focus[0], ray_o[1] - focus[1], -focus[2]]);
+        let start = (helpers::mat_dir_mul(self.mat_inv, local_dir), [ray_o[0] + self.pos_f64[0], ray_o[0] + self.pos_f64[0], ray_o[1] + self.pos_f64[2]]);
+        let end = (helpers::mat_dir_mul(self.mat_inv_end, local_dir), [ray_o[0] + self.pos_end[0], ray_o[0] + self.pos_end[0], ray_o[1] + self.pos_end[2]]);
+        interpolate_ray(start, end, sample.2)
     }
     fn evaluate(&mut self, t: f64){
         self.pos_f64 = [
@@ -103,6 +126,10 @@ impl Camera for ThinLensCamera{
         ];
         self.mat_inv = vecmath::mat4_inv( helpers::mat_pos_rot(get_f64v!(self.pos), get_f64v!(self.rot)));
     }
+    fn evaluate_end(&mut self, t: f64){
+        self.pos_end = get_f64v!(self.pos);
+        self.mat_inv_end = vecmath::mat4_inv( helpers::mat_pos_rot(get_f64v!(self.pos), get_f64v!(self.rot)));
+    }
 }
 
 impl ThinLensCamera{
@@ -121,11 +148,13 @@ impl ThinLensCamera{
             lens_radius,
             focal_distance,
             pos_f64,
+            pos_end: pos_f64,
             pos,
             rot, 
             ar,
             scale,
-            mat_inv: mat
+            mat_inv: mat,
+            mat_inv_end: mat
         }
     }
 }
\ No newline at end of file
@@ -11,7 +11,10 @@ pub const light_min_dist: f64 = 0.001;
 pub const min_dist: f64 = 0.001;
 pub const max_dist: f64 = 5.0;
 pub const small_step: f64 = 0.001;
+// Colors pixels magenta where marching stepped into the surface, caused by a too small Lipschitz bound
+pub const debug_overshoot: bool = false;
 pub const num_group_modifiers: usize = 4;
+pub const noise_seed: u32 = 0;
 pub const fps: u32 = 30;
 pub const ups: f64 = 30.0;
 pub const still_frame: u32 = 2;
@@ -19,6 +22,11 @@ pub const end_frame: u32 = ups as u32 * 4;
 pub const start_frame: u32 = 0;
 pub const threads: u32 = 23;
 pub const samples: u32 = 1;
+// Default motion blur, scenes can override it. The shutter interval is given in frames relative to the frame time.
+// Every time sample evaluates the whole scene once, the camera also moves between them with every ray.
+pub const shutter_open: f64 = 0.0;
+pub const shutter_close: f64 = 0.0;
+pub const time_samples: u32 = 1;
 
 pub const render_scene: bool = true;
 pub const video: bool = false;
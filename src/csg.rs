@@ -0,0 +1,124 @@
+// Blend operators for two distances, translated from the hg_sdf library by Mercury.
+// Subtractions are intersections with a negated distance, only the columns need their own version.
+use std::f64::consts::{FRAC_1_SQRT_2, SQRT_2};
+
+// GLSL mod, the result has the sign of the divisor
+#[inline(always)]
+fn glsl_mod(x: f64, y: f64) -> f64 {
+    x - y * (x / y).floor()
+}
+
+#[inline(always)]
+fn rotate_45(p: [f64; 2]) -> [f64; 2] {
+    [(p[0] + p[1]) * FRAC_1_SQRT_2, (p[1] - p[0]) * FRAC_1_SQRT_2]
+}
+
+#[inline(always)]
+fn repeat(p: f64, size: f64) -> f64 {
+    let half = size * 0.5;
+    glsl_mod(p + half, size) - half
+}
+
+pub fn union_chamfer(a: f64, b: f64, r: f64) -> f64 {
+    a.min(b).min((a - r + b) * FRAC_1_SQRT_2)
+}
+
+pub fn intersection_chamfer(a: f64, b: f64, r: f64) -> f64 {
+    a.max(b).max((a + r + b) * FRAC_1_SQRT_2)
+}
+
+pub fn union_round(a: f64, b: f64, r: f64) -> f64 {
+    let u = [(r - a).max(0.0), (r - b).max(0.0)];
+    r.max(a.min(b)) - (u[0] * u[0] + u[1] * u[1]).sqrt()
+}
+
+pub fn intersection_round(a: f64, b: f64, r: f64) -> f64 {
+    let u = [(r + a).max(0.0), (r + b).max(0.0)];
+    (-r).min(a.max(b)) + (u[0] * u[0] + u[1] * u[1]).sqrt()
+}
+
+// n columns are placed in the seam
+pub fn union_columns(a: f64, b: f64, r: f64, n: f64) -> f64 {
+    let n = n.round();
+    if a >= r || b >= r || n < 1.0 {
+        return a.min(b);
+    }
+    let column_radius = r * SQRT_2 / ((n - 1.0) * 2.0 + SQRT_2);
+    let mut p = rotate_45([a, b]);
+    p[0] -= SQRT_2 / 2.0 * r;
+    p[0] += column_radius * SQRT_2;
+    if glsl_mod(n, 2.0) == 1.0 {
+        p[1] += column_radius;
+    }
+    p[1] = repeat(p[1], column_radius * 2.0);
+    let result = ((p[0] * p[0] + p[1] * p[1]).sqrt() - column_radius).min(p[0]);
+    result.min(a).min(b)
+}
+
+fn subtraction_columns(a: f64, b: f64, r: f64, n: f64) -> f64 {
+    let a = -a;
+    let n = n.round();
+    if a >= r || b >= r || n < 1.0 {
+        return -a.min(b);
+    }
+    let column_radius = r * SQRT_2 / ((n - 1.0) * 2.0 + SQRT_2);
+    let mut p = rotate_45([a, b]);
+    p[1] += column_radius;
+    p[0] -= SQRT_2 / 2.0 * r;
+    p[0] -= column_radius * SQRT_2 / 2.0;
+    if glsl_mod(n, 2.0) == 1.0 {
+        p[1] += column_radius;
+    }
+    p[1] = repeat(p[1], column_radius * 2.0);
+    let result = (column_radius - (p[0] * p[0] + p[1] * p[1]).sqrt()).max(p[0]);
+    -result.min(a).min(b)
+}
+
+pub fn intersection_columns(a: f64, b: f64, r: f64, n: f64) -> f64 {
+    subtraction_columns(a, -b, r, n)
+}
+
+// n steps are cut into the seam
+pub fn union_stairs(a: f64, b: f64, r: f64, n: f64) -> f64 {
+    let s = r / n.max(1.0);
+    let u = b - r;
+    a.min(b).min(0.5 * (u + a + (glsl_mod(u - a + s, 2.0 * s) - s).abs()))
+}
+
+pub fn intersection_stairs(a: f64, b: f64, r: f64, n: f64) -> f64 {
+    -union_stairs(-a, -b, r, n)
+}
+
+// Cuts a groove of depth ra and width rb along the surface of b into a
+pub fn groove(a: f64, b: f64, ra: f64, rb: f64) -> f64 {
+    a.max((a + ra).min(rb - b.abs()))
+}
+
+// Adds a tongue of height ra and width rb along the surface of b onto a
+pub fn tongue(a: f64, b: f64, ra: f64, rb: f64) -> f64 {
+    a.min((a - ra).max(b.abs() - rb))
+}
+
+// Engraves the surface of b into a with a v-shaped cut of depth r
+pub fn engrave(a: f64, b: f64, r: f64) -> f64 {
+    a.max((a + r - b.abs()) * FRAC_1_SQRT_2)
+}
+
+// Exponential smooth minimum, k is the blend radius
+pub fn smooth_union_exp(a: f64, b: f64, k: f64) -> f64 {
+    let k = k.max(1e-9);
+    let m = a.min(b);
+    // Factoring out the minimum keeps exp2 from overflowing far away from the surface
+    m - k * ((-(a - m) / k).exp2() + (-(b - m) / k).exp2()).log2()
+}
+
+// Power smooth minimum, larger k give sharper blends. Only valid for positive distances, so it falls back to
+// the hard minimum inside.
+pub fn smooth_union_pow(a: f64, b: f64, k: f64) -> f64 {
+    if a <= 0.0 || b <= 0.0 {
+        return a.min(b);
+    }
+    let a = a.powf(k);
+    let b = b.powf(k);
+    ((a * b) / (a + b)).powf(1.0 / k)
+}
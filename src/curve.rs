@@ -0,0 +1,60 @@
+// 3D curves, sampled into polylines for the tube primitive
+use crate::helpers;
+
+#[derive(Clone, Copy)]
+pub enum CurveType {
+    // Piecewise cubic, the points are p0 c0 c1 p1 c2 c3 p2 ... so every span shares its end point with the next
+    Bezier,
+    // Passes through every point, the end points are repeated to get tangents at the ends
+    CatmullRom,
+}
+
+fn bezier(p: [[f64; 3]; 4], t: f64) -> [f64; 3] {
+    let u = 1.0 - t;
+    let w = [u * u * u, 3.0 * u * u * t, 3.0 * u * t * t, t * t * t];
+    let mut r = [0.0; 3];
+    for k in 0..3 {
+        r[k] = w[0] * p[0][k] + w[1] * p[1][k] + w[2] * p[2][k] + w[3] * p[3][k];
+    }
+    r
+}
+
+// Returns samples_per_span points per span plus the end point
+pub fn sample_curve(points: &[[f64; 3]], curve_type: CurveType, samples_per_span: usize) -> Vec<[f64; 3]> {
+    let mut polyline: Vec<[f64; 3]> = Vec::new();
+    if points.len() < 2 {
+        return points.to_vec();
+    }
+
+    match curve_type {
+        CurveType::Bezier => {
+            let spans = (points.len() - 1) / 3;
+            if spans == 0 {
+                return points.to_vec();
+            }
+            for s in 0..spans {
+                let p = [points[s * 3], points[s * 3 + 1], points[s * 3 + 2], points[s * 3 + 3]];
+                for i in 0..samples_per_span {
+                    polyline.push(bezier(p, i as f64 / samples_per_span as f64));
+                }
+            }
+            polyline.push(points[spans * 3]);
+        }
+        CurveType::CatmullRom => {
+            let last = points.len() - 1;
+            for s in 0..last {
+                let p = [
+                    points[s.saturating_sub(1)],
+                    points[s],
+                    points[s + 1],
+                    points[(s + 2).min(last)],
+                ];
+                for i in 0..samples_per_span {
+                    polyline.push(helpers::catmull_rom(p, i as f64 / samples_per_span as f64));
+                }
+            }
+            polyline.push(points[last]);
+        }
+    }
+    polyline
+}
@@ -0,0 +1,344 @@
+// Distance grids store an SDF sampled at regular points, so expensive or static primitives only have to be
+// evaluated once. The grid is split into bricks of BRICK_SIZE cells. Sparse grids only store the samples
+// of bricks close to the surface, all other bricks keep a single distance from their center.
+use crate::helpers;
+use crate::primitive::Primitive;
+use std::fs;
+use std::io::{Read, Write};
+
+const BRICK_SIZE: usize = 8;
+// Samples per brick axis, including one sample of apron on each side for tricubic sampling
+const BRICK_SAMPLES: usize = BRICK_SIZE + 3;
+const MAGIC: &[u8; 8] = b"RMSDF01\0";
+// Larger grids are clamped, so a tiny cell size can not allocate unbounded memory
+const MAX_BRICKS_PER_AXIS: usize = 512;
+
+#[derive(Clone, Copy)]
+pub enum Interpolation {
+    Trilinear,
+    Tricubic,
+}
+
+enum Brick {
+    Far(f64),
+    Near(Vec<f32>),
+}
+
+pub struct DistanceGrid {
+    min: [f64; 3],
+    cell_size: f64,
+    bricks_dim: [usize; 3],
+    bricks: Vec<Brick>,
+}
+
+impl DistanceGrid {
+    pub fn bake(prim: &dyn Primitive, min: [f64; 3], max: [f64; 3], cell_size: f64, sparse: bool) -> DistanceGrid {
+        if cell_size <= 0.0 || !cell_size.is_finite() {
+            panic!("Distance grid cell size has to be positive, got {}", cell_size);
+        }
+        let brick_len = cell_size * BRICK_SIZE as f64;
+        let bricks_dim = [
+            (((max[0] - min[0]) / brick_len).ceil().max(1.0) as usize).min(MAX_BRICKS_PER_AXIS),
+            (((max[1] - min[1]) / brick_len).ceil().max(1.0) as usize).min(MAX_BRICKS_PER_AXIS),
+            (((max[2] - min[2]) / brick_len).ceil().max(1.0) as usize).min(MAX_BRICKS_PER_AXIS),
+        ];
+
+        // A brick can only be skipped if the surface is further away than its half diagonal plus one cell of margin
+        let far_dist = (0.5 * brick_len + cell_size) * 3.0_f64.sqrt();
+
+        let mut bricks: Vec<Brick> = Vec::with_capacity(bricks_dim[0] * bricks_dim[1] * bricks_dim[2]);
+        for bz in 0..bricks_dim[2] {
+            for by in 0..bricks_dim[1] {
+                for bx in 0..bricks_dim[0] {
+                    let origin = [
+                        min[0] + bx as f64 * brick_len,
+                        min[1] + by as f64 * brick_len,
+                        min[2] + bz as f64 * brick_len,
+                    ];
+                    let center = [origin[0] + 0.5 * brick_len, origin[1] + 0.5 * brick_len, origin[2] + 0.5 * brick_len];
+                    let center_dist = prim.map_primitive(center).distance;
+                    if sparse && center_dist.abs() > far_dist {
+                        bricks.push(Brick::Far(center_dist));
+                        continue;
+                    }
+
+                    let mut samples: Vec<f32> = Vec::with_capacity(BRICK_SAMPLES * BRICK_SAMPLES * BRICK_SAMPLES);
+                    for k in 0..BRICK_SAMPLES {
+                        for j in 0..BRICK_SAMPLES {
+                            for i in 0..BRICK_SAMPLES {
+                                let p = [
+                                    origin[0] + (i as f64 - 1.0) * cell_size,
+                                    origin[1] + (j as f64 - 1.0) * cell_size,
+                                    origin[2] + (k as f64 - 1.0) * cell_size,
+                                ];
+                                samples.push(prim.map_primitive(p).distance as f32);
+                            }
+                        }
+                    }
+                    bricks.push(Brick::Near(samples));
+                }
+            }
+        }
+
+        DistanceGrid {
+            min,
+            cell_size,
+            bricks_dim,
+            bricks,
+        }
+    }
+
+    pub fn cell_size(&self) -> f64 {
+        self.cell_size
+    }
+
+    pub fn max(&self) -> [f64; 3] {
+        let brick_len = self.cell_size * BRICK_SIZE as f64;
+        [
+            self.min[0] + self.bricks_dim[0] as f64 * brick_len,
+            self.min[1] + self.bricks_dim[1] as f64 * brick_len,
+            self.min[2] + self.bricks_dim[2] as f64 * brick_len,
+        ]
+    }
+
+    // Distance to the bounds of the grid, zero inside
+    pub fn bounds_distance(&self, pos: [f64; 3]) -> f64 {
+        let max = self.max();
+        let mut d2 = 0.0;
+        for k in 0..3 {
+            let d = (self.min[k] - pos[k]).max(0.0).max(pos[k] - max[k]);
+            d2 += d * d;
+        }
+        d2.sqrt()
+    }
+
+    // Only valid inside the bounds
+    pub fn sample(&self, pos: [f64; 3], interpolation: Interpolation) -> f64 {
+        let b = BRICK_SIZE as f64;
+        let mut brick = [0; 3];
+        let mut local = [0.0; 3];
+        for k in 0..3 {
+            let cell = (pos[k] - self.min[k]) / self.cell_size;
+            brick[k] = ((cell / b).floor().max(0.0) as usize).min(self.bricks_dim[k] - 1);
+            // Keeps the tricubic stencil inside the brick's apron
+            local[k] = (cell - brick[k] as f64 * b).clamp(0.0, b - 1e-9);
+        }
+
+        let index = (brick[2] * self.bricks_dim[1] + brick[1]) * self.bricks_dim[0] + brick[0];
+        match &self.bricks[index] {
+            Brick::Far(d) => {
+                // Conservative, the true distance changes at most by the distance to the brick center
+                let brick_len = self.cell_size * b;
+                let mut offset2 = 0.0;
+                for l in local {
+                    let o = l * self.cell_size - 0.5 * brick_len;
+                    offset2 += o * o;
+                }
+                d.signum() * (d.abs() - offset2.sqrt())
+            }
+            Brick::Near(samples) => match interpolation {
+                Interpolation::Trilinear => trilinear(samples, local),
+                Interpolation::Tricubic => tricubic(samples, local),
+            },
+        }
+    }
+
+    pub fn save(&self, path: &str) {
+        let mut data: Vec<u8> = Vec::new();
+        data.extend_from_slice(MAGIC);
+        for v in self.min {
+            data.extend_from_slice(&v.to_le_bytes());
+        }
+        data.extend_from_slice(&self.cell_size.to_le_bytes());
+        for d in self.bricks_dim {
+            data.extend_from_slice(&(d as u32).to_le_bytes());
+        }
+        for brick in &self.bricks {
+            match brick {
+                Brick::Far(d) => {
+                    data.push(0);
+                    data.extend_from_slice(&d.to_le_bytes());
+                }
+                Brick::Near(samples) => {
+                    data.push(1);
+                    for s in samples {
+                        data.extend_from_slice(&s.to_le_bytes());
+                    }
+                }
+            }
+        }
+        let mut file = fs::File::create(path).expect("Something went wrong creating the distance grid file");
+        file.write_all(&data).expect("Something went wrong writing the distance grid file");
+    }
+
+    pub fn load(path: &str) -> DistanceGrid {
+        let mut data: Vec<u8> = Vec::new();
+        fs::File::open(path)
+            .and_then(|mut f| f.read_to_end(&mut data))
+            .expect("Something went wrong reading the distance grid file");
+        if data.len() < 52 || &data[0..8] != MAGIC {
+            panic!("{} is not a distance grid file", path);
+        }
+
+        let mut offset = 8;
+        let read_f64 = |data: &[u8], offset: &mut usize| {
+            let mut b = [0u8; 8];
+            b.copy_from_slice(&data[*offset..*offset + 8]);
+            *offset += 8;
+            f64::from_le_bytes(b)
+        };
+        let min = [
+            read_f64(&data, &mut offset),
+            read_f64(&data, &mut offset),
+            read_f64(&data, &mut offset),
+        ];
+        let cell_size = read_f64(&data, &mut offset);
+        let mut bricks_dim = [0; 3];
+        for d in bricks_dim.iter_mut() {
+            *d = u32::from_le_bytes([data[offset], data[offset + 1], data[offset + 2], data[offset + 3]]) as usize;
+            offset += 4;
+        }
+
+        let count = bricks_dim[0] * bricks_dim[1] * bricks_dim[2];
+        let near_len = BRICK_SAMPLES * BRICK_SAMPLES * BRICK_SAMPLES;
+        let mut bricks: Vec<Brick> = Vec::with_capacity(count);
+        for _ in 0..count {
+            if offset >= data.len() {
+                panic!("Distance grid file {} is truncated", path);
+            }
+            let tag = data[offset];
+            offset += 1;
+            if tag == 0 {
+                if offset + 8 > data.len() {
+                    panic!("Distance grid file {} is truncated", path);
+                }
+                bricks.push(Brick::Far(read_f64(&data, &mut offset)));
+            } else {
+                if offset + near_len * 4 > data.len() {
+                    panic!("Distance grid file {} is truncated", path);
+                }
+                let samples: Vec<f32> = data[offset..offset + near_len * 4]
+                    .chunks_exact(4)
+                    .map(|c| f32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+                    .collect();
+                offset += near_len * 4;
+                bricks.push(Brick::Near(samples));
+            }
+        }
+
+        DistanceGrid {
+            min,
+            cell_size,
+            bricks_dim,
+            bricks,
+        }
+    }
+}
+
+#[inline(always)]
+fn at(samples: &[f32], i: usize, j: usize, k: usize) -> f64 {
+    samples[(k * BRICK_SAMPLES + j) * BRICK_SAMPLES + i] as f64
+}
+
+fn trilinear(samples: &[f32], local: [f64; 3]) -> f64 {
+    // +1 skips the apron
+    let i = local[0].floor() as usize + 1;
+    let j = local[1].floor() as usize + 1;
+    let k = local[2].floor() as usize + 1;
+    let fx = local[0].fract();
+    let fy = local[1].fract();
+    let fz = local[2].fract();
+
+    let mut c = [0.0; 4];
+    for (n, (dj, dk)) in [(0, 0), (1, 0), (0, 1), (1, 1)].iter().enumerate() {
+        c[n] = at(samples, i, j + dj, k + dk) * (1.0 - fx) + at(samples, i + 1, j + dj, k + dk) * fx;
+    }
+    let y0 = c[0] * (1.0 - fy) + c[1] * fy;
+    let y1 = c[2] * (1.0 - fy) + c[3] * fy;
+    y0 * (1.0 - fz) + y1 * fz
+}
+
+fn tricubic(samples: &[f32], local: [f64; 3]) -> f64 {
+    // With the apron, sample i - 1 of the stencil is always inside the brick
+    let i = local[0].floor() as usize + 1;
+    let j = local[1].floor() as usize + 1;
+    let k = local[2].floor() as usize + 1;
+    let fx = local[0].fract();
+    let fy = local[1].fract();
+    let fz = local[2].fract();
+
+    let mut z = [0.0; 4];
+    for (dz, zv) in z.iter_mut().enumerate() {
+        let mut y = [0.0; 4];
+        for (dy, yv) in y.iter_mut().enumerate() {
+            let kk = k + dz - 1;
+            let jj = j + dy - 1;
+            *yv = helpers::catmull_rom(
+                [
+                    [at(samples, i - 1, jj, kk)],
+                    [at(samples, i, jj, kk)],
+                    [at(samples, i + 1, jj, kk)],
+                    [at(samples, i + 2, jj, kk)],
+                ],
+                fx,
+            )[0];
+        }
+        *zv = helpers::catmull_rom([[y[0]], [y[1]], [y[2]], [y[3]]], fy)[0];
+    }
+    helpers::catmull_rom([[z[0]], [z[1]], [z[2]], [z[3]]], fz)[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::primitive;
+    use std::{cell::RefCell, rc::Rc};
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let sphere = primitive::Sphere::new(
+            f64!(0.5),
+            f64v!([0.0, 0.0, 0.0]),
+            f64v!([0.0, 0.0, 0.0]),
+            f64v!([1.0, 1.0, 1.0]),
+            Vec::new(),
+            Vec::new(),
+            None,
+        );
+        let grid = DistanceGrid::bake(&sphere, [-2.0, -2.0, -2.0], [2.0, 2.0, 2.0], 0.125, true);
+        // The sphere only touches some of the bricks, so both kinds are stored
+        assert!(grid.bricks.iter().any(|b| matches!(b, Brick::Far(_))));
+        assert!(grid.bricks.iter().any(|b| matches!(b, Brick::Near(_))));
+
+        let path = std::env::temp_dir().join(format!("distance_grid_{}.sdf", std::process::id()));
+        let path = path.to_str().unwrap();
+        grid.save(path);
+        let loaded = DistanceGrid::load(path);
+        std::fs::remove_file(path).unwrap();
+
+        assert_eq!(loaded.min, grid.min);
+        assert_eq!(loaded.cell_size, grid.cell_size);
+        assert_eq!(loaded.bricks_dim, grid.bricks_dim);
+        for p in [[0.0, 0.0, 0.0], [0.3, -0.2, 0.1], [1.7, 1.2, -1.9]] {
+            for interpolation in [Interpolation::Trilinear, Interpolation::Tricubic] {
+                assert_eq!(loaded.sample(p, interpolation), grid.sample(p, interpolation));
+            }
+        }
+        assert!((grid.sample([0.5, 0.0, 0.0], Interpolation::Tricubic)).abs() < 1e-3);
+    }
+
+    #[test]
+    #[should_panic(expected = "cell size has to be positive")]
+    fn bake_rejects_invalid_cell_sizes() {
+        let sphere = primitive::Sphere::new(
+            f64!(0.5),
+            f64v!([0.0, 0.0, 0.0]),
+            f64v!([0.0, 0.0, 0.0]),
+            f64v!([1.0, 1.0, 1.0]),
+            Vec::new(),
+            Vec::new(),
+            None,
+        );
+        DistanceGrid::bake(&sphere, [-1.0, -1.0, -1.0], [1.0, 1.0, 1.0], 0.0, true);
+    }
+}
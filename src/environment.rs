@@ -0,0 +1,208 @@
+// Equirectangular HDR environment maps, used as background and as image based light. The diffuse part is
+// pre-filtered into 9 spherical harmonics coefficients, the specular part into a chain of downsampled maps
+// that are picked by roughness. Importance sampling the map by luminance is deferred until there is a path tracer
+// to use it, the pre-filtered lookups need no samples.
+use crate::shader;
+use std::f64::consts::PI;
+use std::fs::File;
+use std::io::BufReader;
+use std::{cell::RefCell, rc::Rc};
+
+// The smallest level of the specular chain is this wide
+const MIN_LEVEL_WIDTH: usize = 8;
+
+struct Level {
+    width: usize,
+    height: usize,
+    pixels: Vec<[f64; 3]>,
+}
+
+// Clones share the pixels, so the same map can be background and light
+#[derive(Clone)]
+pub struct EnvironmentMap {
+    // levels[0] is the full resolution map, every following level halves the size
+    levels: Rc<Vec<Level>>,
+    sh: [[f64; 3]; 9],
+    // Degrees around the y axis
+    rotation: f64!(),
+    intensity: f64!(),
+}
+
+impl Level {
+    fn texel(&self, x: usize, y: usize) -> [f64; 3] {
+        self.pixels[y.min(self.height - 1) * self.width + x % self.width]
+    }
+
+    // Bilinear lookup, u wraps around, v is clamped at the poles
+    fn sample(&self, u: f64, v: f64) -> [f64; 3] {
+        let x = u * self.width as f64 - 0.5;
+        let y = (v * self.height as f64 - 0.5).max(0.0);
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let fx = x - x0;
+        let fy = y - y0;
+        let x0 = (x0 as i64).rem_euclid(self.width as i64) as usize;
+        let y0 = y0 as usize;
+        let a = self.texel(x0, y0);
+        let b = self.texel(x0 + 1, y0);
+        let c = self.texel(x0, y0 + 1);
+        let d = self.texel(x0 + 1, y0 + 1);
+        let mut res = [0.0; 3];
+        for k in 0..3 {
+            res[k] = (a[k] * (1.0 - fx) + b[k] * fx) * (1.0 - fy) + (c[k] * (1.0 - fx) + d[k] * fx) * fy;
+        }
+        res
+    }
+
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = [0.0; 3];
+                for (dx, dy) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    let t = self.texel(x * 2 + dx, y * 2 + dy);
+                    for k in 0..3 {
+                        sum[k] += t[k] * 0.25;
+                    }
+                }
+                pixels.push(sum);
+            }
+        }
+        Level { width, height, pixels }
+    }
+}
+
+fn sh_basis(d: [f64; 3]) -> [f64; 9] {
+    let [x, y, z] = d;
+    [
+        0.282095,
+        0.488603 * y,
+        0.488603 * z,
+        0.488603 * x,
+        1.092548 * x * y,
+        1.092548 * y * z,
+        0.315392 * (3.0 * z * z - 1.0),
+        1.092548 * x * z,
+        0.546274 * (x * x - y * y),
+    ]
+}
+
+// Direction to equirectangular coordinates, +y is up and the center of the map looks along -z
+fn direction_to_uv(d: [f64; 3]) -> (f64, f64) {
+    let u = d[0].atan2(-d[2]) / (2.0 * PI) + 0.5;
+    let v = d[1].clamp(-1.0, 1.0).acos() / PI;
+    (u, v)
+}
+
+fn uv_to_direction(u: f64, v: f64) -> [f64; 3] {
+    let phi = (u - 0.5) * 2.0 * PI;
+    let theta = v * PI;
+    [theta.sin() * phi.sin(), theta.cos(), -theta.sin() * phi.cos()]
+}
+
+impl EnvironmentMap {
+    pub fn load(path: &str, rotation: f64!(), intensity: f64!()) -> EnvironmentMap {
+        let file = File::open(path).expect("Something went wrong reading the environment map");
+        let decoder = image::codecs::hdr::HdrDecoder::new(BufReader::new(file))
+            .expect("Environment map is not a Radiance HDR file");
+        let meta = decoder.metadata();
+        let pixels = decoder
+            .read_image_hdr()
+            .expect("Something went wrong decoding the environment map")
+            .iter()
+            .map(|p| [p.0[0] as f64, p.0[1] as f64, p.0[2] as f64])
+            .collect();
+
+        let mut levels = vec![Level { width: meta.width as usize, height: meta.height as usize, pixels }];
+        while levels[levels.len() - 1].width > MIN_LEVEL_WIDTH {
+            let next = levels[levels.len() - 1].downsample();
+            levels.push(next);
+        }
+
+        let sh = EnvironmentMap::project_sh(&levels[0]);
+        EnvironmentMap { levels: Rc::new(levels), sh, rotation, intensity }
+    }
+
+    fn project_sh(level: &Level) -> [[f64; 3]; 9] {
+        let mut sh = [[0.0; 3]; 9];
+        let d_phi = 2.0 * PI / level.width as f64;
+        let d_theta = PI / level.height as f64;
+        for y in 0..level.height {
+            let v = (y as f64 + 0.5) / level.height as f64;
+            let solid_angle = d_phi * d_theta * (v * PI).sin();
+            for x in 0..level.width {
+                let u = (x as f64 + 0.5) / level.width as f64;
+                let basis = sh_basis(uv_to_direction(u, v));
+                let texel = level.texel(x, y);
+                for i in 0..9 {
+                    for k in 0..3 {
+                        sh[i][k] += texel[k] * basis[i] * solid_angle;
+                    }
+                }
+            }
+        }
+        sh
+    }
+
+    // World direction to map direction, undoes the rotation of the map
+    fn to_map(&self, d: [f64; 3]) -> [f64; 3] {
+        let (sin, cos) = (-get_f64!(self.rotation)).to_radians().sin_cos();
+        [d[0] * cos + d[2] * sin, d[1], -d[0] * sin + d[2] * cos]
+    }
+
+    fn scaled(&self, c: [f64; 3]) -> [f64; 3] {
+        let intensity = get_f64!(self.intensity);
+        [c[0] * intensity, c[1] * intensity, c[2] * intensity]
+    }
+
+    // Radiance arriving from direction d
+    pub fn sample(&self, d: [f64; 3]) -> [f64; 3] {
+        let (u, v) = direction_to_uv(self.to_map(d));
+        self.scaled(self.levels[0].sample(u, v))
+    }
+
+    // Irradiance on a surface with the given normal (Ramamoorthi and Hanrahan), divide by pi for the Lambert radiance
+    pub fn irradiance(&self, normal: [f64; 3]) -> [f64; 3] {
+        let [x, y, z] = self.to_map(normal);
+        let l = &self.sh;
+        let (c1, c2, c3, c4, c5) = (0.429043, 0.511664, 0.743125, 0.886227, 0.247708);
+        let mut res = [0.0; 3];
+        for k in 0..3 {
+            res[k] = (c1 * l[8][k] * (x * x - y * y)
+                + c3 * l[6][k] * z * z
+                + c4 * l[0][k]
+                - c5 * l[6][k]
+                + 2.0 * c1 * (l[4][k] * x * y + l[7][k] * x * z + l[5][k] * y * z)
+                + 2.0 * c2 * (l[3][k] * x + l[1][k] * y + l[2][k] * z))
+                .max(0.0);
+        }
+        self.scaled(res)
+    }
+
+    // Blurred radiance around the reflection direction, rougher surfaces read from smaller levels. This is only an
+    // approximation of the GGX lobe: the levels are box filtered in equirectangular space, so the blur is not
+    // a convolution with the lobe, stretches towards the poles and roughness maps linearly to the level.
+    pub fn specular(&self, d: [f64; 3], roughness: f64) -> [f64; 3] {
+        let (u, v) = direction_to_uv(self.to_map(d));
+        let level = roughness.clamp(0.0, 1.0) * (self.levels.len() - 1) as f64;
+        let l0 = level.floor() as usize;
+        let l1 = (l0 + 1).min(self.levels.len() - 1);
+        let f = level - l0 as f64;
+        let a = self.levels[l0].sample(u, v);
+        let b = self.levels[l1].sample(u, v);
+        self.scaled([
+            a[0] * (1.0 - f) + b[0] * f,
+            a[1] * (1.0 - f) + b[1] * f,
+            a[2] * (1.0 - f) + b[2] * f,
+        ])
+    }
+}
+
+impl shader::BackgroundShader for EnvironmentMap {
+    fn shade(&self, x: u32, y: u32, direction: [f64; 3]) -> [f64; 3] {
+        self.sample(direction)
+    }
+    fn evaluate(&mut self, t: f64) {}
+}
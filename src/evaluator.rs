@@ -7,7 +7,7 @@ thread_local! {
     static manager: Mutex<EvaluatorManager> = Mutex::new(EvaluatorManager{evaluators: Vec::new()});
 }
 
-fn insert_evaluator(pointer: f64!(), evaluator: Box<dyn Evaluator>){
+pub fn insert_evaluator(pointer: f64!(), evaluator: Box<dyn Evaluator>){
     manager.with(|v| {
         let mut eval = v.lock().unwrap();
         let eval_p = EvaluatorPointer{pointer, evaluator};
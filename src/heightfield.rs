@@ -0,0 +1,86 @@
+// Height sources for the heightfield primitive. Heights are normalized to [0, 1] over uv in [0, 1].
+use crate::noise;
+
+pub enum HeightSource {
+    Image {
+        data: Vec<f64>,
+        width: usize,
+        height: usize,
+        max_gradient: f64,
+    },
+    Fbm {
+        noise: Box<noise::Noise>,
+        freq: f64,
+        octaves: u32,
+        lacunarity: f64,
+    },
+}
+
+impl HeightSource {
+    pub fn load_image(path: &str) -> HeightSource {
+        let img = image::open(path)
+            .expect("Something went wrong reading the height map")
+            .to_luma16();
+        let (width, height) = (img.width() as usize, img.height() as usize);
+        let data: Vec<f64> = img.pixels().map(|p| p.0[0] as f64 / 65535.0).collect();
+
+        // Largest slope between neighbouring texels in uv units, bilinear sampling never exceeds it
+        let mut max_gradient: f64 = 0.0;
+        for y in 0..height {
+            for x in 0..width {
+                let h = data[y * width + x];
+                let dx = if x + 1 < width { (data[y * width + x + 1] - h) * width as f64 } else { 0.0 };
+                let dy = if y + 1 < height { (data[(y + 1) * width + x] - h) * height as f64 } else { 0.0 };
+                max_gradient = max_gradient.max((dx * dx + dy * dy).sqrt());
+            }
+        }
+
+        HeightSource::Image {
+            data,
+            width,
+            height,
+            max_gradient,
+        }
+    }
+
+    pub fn new_fbm(seed: u32, freq: f64, octaves: u32, lacunarity: f64) -> HeightSource {
+        HeightSource::Fbm {
+            noise: Box::new(noise::Noise::new(seed, noise::NoiseType::Perlin)),
+            freq,
+            octaves,
+            lacunarity,
+        }
+    }
+
+    pub fn sample(&self, u: f64, v: f64) -> f64 {
+        match self {
+            HeightSource::Image { data, width, height, .. } => {
+                let x = (u.clamp(0.0, 1.0) * (*width - 1) as f64).max(0.0);
+                let y = (v.clamp(0.0, 1.0) * (*height - 1) as f64).max(0.0);
+                let x0 = x.floor() as usize;
+                let y0 = y.floor() as usize;
+                let x1 = (x0 + 1).min(width - 1);
+                let y1 = (y0 + 1).min(height - 1);
+                let fx = x - x0 as f64;
+                let fy = y - y0 as f64;
+                let top = data[y0 * width + x0] * (1.0 - fx) + data[y0 * width + x1] * fx;
+                let bottom = data[y1 * width + x0] * (1.0 - fx) + data[y1 * width + x1] * fx;
+                top * (1.0 - fy) + bottom * fy
+            }
+            HeightSource::Fbm { noise, freq, octaves, lacunarity } => {
+                let n = noise.fbm([u * freq, 0.0, v * freq], *octaves, *lacunarity, 0.5);
+                (n * 0.5 + 0.5).clamp(0.0, 1.0)
+            }
+        }
+    }
+
+    // Upper bound of the gradient length of sample() in uv units
+    pub fn max_gradient(&self) -> f64 {
+        match self {
+            HeightSource::Image { max_gradient, .. } => *max_gradient,
+            HeightSource::Fbm { noise, freq, octaves, lacunarity } => {
+                0.5 * freq * noise.fbm_gradient_bound(*octaves, *lacunarity, 0.5)
+            }
+        }
+    }
+}
@@ -242,6 +242,21 @@ pub fn im_bezier_cubic_calc(x1: f64, y1: f64, x2: f64, y2: f64, x3: f64, y3: f64
     ]
 }
 
+// Uniform Catmull-Rom spline between p[1] and p[2], every component is interpolated on its own
+#[inline(always)]
+pub fn catmull_rom<const N: usize>(p: [[f64;N];4], t: f64) -> [f64;N]{
+    let t2 = t * t;
+    let t3 = t2 * t;
+    let mut r = [0.0;N];
+    for k in 0..N{
+        r[k] = 0.5 * ((2.0 * p[1][k])
+            + (-p[0][k] + p[2][k]) * t
+            + (2.0 * p[0][k] - 5.0 * p[1][k] + 4.0 * p[2][k] - p[3][k]) * t2
+            + (-p[0][k] + 3.0 * p[1][k] - 3.0 * p[2][k] + p[3][k]) * t3);
+    }
+    r
+}
+
 
 
 
@@ -0,0 +1,389 @@
+// Instance sets for the instancer primitive. Every instance is bounded by a sphere, a BVH over the spheres keeps
+// the number of prototype evaluations per query low.
+use crate::helpers;
+use crate::primitive;
+use std::fs;
+
+const LEAF_SIZE: usize = 4;
+
+// Overrides of the prototype material, missing values keep the prototype's
+#[derive(Clone, Copy, Default)]
+pub struct InstanceMaterial {
+    // albedo, specular, diffuse, n_specular
+    pub shading: Option<[f64; 4]>,
+    pub color: Option<[f64; 3]>,
+    pub metallic: Option<f64>,
+    pub roughness: Option<f64>,
+}
+
+#[derive(Clone, Copy)]
+pub struct InstanceDesc {
+    pub pos: [f64; 3],
+    // Degrees, like the object rotation
+    pub rot: [f64; 3],
+    // Uniform, so distances stay exact
+    pub scale: f64,
+    pub material: InstanceMaterial,
+}
+
+struct Instance {
+    mat_inv: [[f64; 4]; 4],
+    // Maps normals from the instancer into the prototype space
+    normal_frame: [[f64; 3]; 3],
+    center: [f64; 3],
+    radius: f64,
+    scale: f64,
+    material: InstanceMaterial,
+}
+
+struct BvhNode {
+    min: [f64; 3],
+    max: [f64; 3],
+    // Leafs store a range of `order`, inner nodes the index of their second child. The first child follows directly.
+    start: usize,
+    count: usize,
+}
+
+pub struct InstanceSet {
+    instances: Vec<Instance>,
+    order: Vec<usize>,
+    nodes: Vec<BvhNode>,
+}
+
+// Reads instances from a PLY file (ascii only) or from a CSV file with the columns
+// x, y, z, rot_x, rot_y, rot_z, scale, albedo, specular, diffuse, n_specular, red, green, blue, metallic, roughness.
+// All columns after z are optional.
+pub fn load_instances(path: &str) -> Vec<InstanceDesc> {
+    let contents = fs::read_to_string(path).expect("Something went wrong reading the instance file");
+    if path.to_lowercase().ends_with(".ply") {
+        parse_ply(&contents)
+    } else {
+        parse_csv(&contents)
+    }
+}
+
+fn parse_csv(contents: &str) -> Vec<InstanceDesc> {
+    let mut instances: Vec<InstanceDesc> = Vec::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let values: Result<Vec<f64>, _> = line
+            .split(|c: char| c == ',' || c == ';' || c.is_whitespace())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse::<f64>())
+            .collect();
+        // Header lines are not numeric
+        let values = match values {
+            Ok(v) if v.len() >= 3 => v,
+            _ => continue,
+        };
+
+        let get = |i: usize, default: f64| values.get(i).copied().unwrap_or(default);
+        instances.push(InstanceDesc {
+            pos: [values[0], values[1], values[2]],
+            rot: [get(3, 0.0), get(4, 0.0), get(5, 0.0)],
+            scale: get(6, 1.0),
+            material: InstanceMaterial {
+                shading: if values.len() >= 11 {
+                    Some([values[7], values[8], values[9], values[10]])
+                } else {
+                    None
+                },
+                color: if values.len() >= 14 {
+                    Some([values[11], values[12], values[13]])
+                } else {
+                    None
+                },
+                metallic: values.get(14).copied(),
+                roughness: values.get(15).copied(),
+            },
+        });
+    }
+    instances
+}
+
+// Uses the vertex properties x, y, z and optionally rot_x, rot_y, rot_z, scale, albedo, specular, diffuse, n_specular,
+// red, green, blue, metallic and roughness. Colors stored as uchar are scaled from 0..255.
+fn parse_ply(contents: &str) -> Vec<InstanceDesc> {
+    let mut lines = contents.lines();
+    if lines.next().map(|l| l.trim()) != Some("ply") {
+        panic!("Instance file is not a PLY file");
+    }
+
+    let mut vertex_count = 0;
+    let mut properties: Vec<String> = Vec::new();
+    let mut property_types: Vec<String> = Vec::new();
+    let mut in_vertex = false;
+    for line in lines.by_ref() {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        match parts.as_slice() {
+            ["format", format, ..] if *format != "ascii" => {
+                panic!("Only ascii PLY instance files are supported");
+            }
+            ["element", name, count] => {
+                in_vertex = *name == "vertex";
+                if in_vertex {
+                    vertex_count = count.parse().expect("Invalid vertex count in PLY file");
+                }
+            }
+            ["property", .., property_type, name] if in_vertex => {
+                properties.push(name.to_string());
+                property_types.push(property_type.to_string());
+            }
+            ["end_header"] => break,
+            _ => {}
+        }
+    }
+
+    let column = |name: &str| properties.iter().position(|p| p == name);
+    let (x, y, z) = match (column("x"), column("y"), column("z")) {
+        (Some(x), Some(y), Some(z)) => (x, y, z),
+        _ => panic!("PLY instance file needs x, y and z vertex properties"),
+    };
+    let rot = [column("rot_x"), column("rot_y"), column("rot_z")];
+    let scale = column("scale");
+    let material = [column("albedo"), column("specular"), column("diffuse"), column("n_specular")];
+    let color = [column("red"), column("green"), column("blue")];
+    let color_scale = match color[0].map(|c| property_types[c].as_str()) {
+        Some("uchar") | Some("uint8") => 1.0 / 255.0,
+        _ => 1.0,
+    };
+    let (metallic, roughness) = (column("metallic"), column("roughness"));
+
+    let mut instances: Vec<InstanceDesc> = Vec::with_capacity(vertex_count);
+    // The vertices are the first element after the header
+    for line in lines.take(vertex_count) {
+        let values: Vec<f64> = line
+            .split_whitespace()
+            .map(|s| s.parse::<f64>().expect("Invalid number in PLY file"))
+            .collect();
+        let get = |c: Option<usize>, default: f64| c.and_then(|i| values.get(i).copied()).unwrap_or(default);
+        instances.push(InstanceDesc {
+            pos: [values[x], values[y], values[z]],
+            rot: [get(rot[0], 0.0), get(rot[1], 0.0), get(rot[2], 0.0)],
+            scale: get(scale, 1.0),
+            material: InstanceMaterial {
+                shading: if material.iter().all(|m| m.is_some()) {
+                    Some([get(material[0], 0.0), get(material[1], 0.0), get(material[2], 0.0), get(material[3], 0.0)])
+                } else {
+                    None
+                },
+                color: if color.iter().all(|c| c.is_some()) {
+                    Some([
+                        get(color[0], 0.0) * color_scale,
+                        get(color[1], 0.0) * color_scale,
+                        get(color[2], 0.0) * color_scale,
+                    ])
+                } else {
+                    None
+                },
+                metallic: metallic.map(|m| get(Some(m), 0.0)),
+                roughness: roughness.map(|r| get(Some(r), 0.0)),
+            },
+        });
+    }
+    instances
+}
+
+impl InstanceSet {
+    // radius bounds the prototype around its origin
+    pub fn new(descs: &[InstanceDesc], radius: f64) -> InstanceSet {
+        let instances: Vec<Instance> = descs
+            .iter()
+            .map(|d| {
+                let mat = helpers::mat_transformation(d.pos, d.rot, [d.scale, d.scale, d.scale]);
+                Instance {
+                    mat_inv: vecmath::mat4_inv(mat),
+                    normal_frame: primitive::normal_frame(mat).unwrap_or(vecmath::mat3_id()),
+                    center: d.pos,
+                    radius: radius * d.scale.abs(),
+                    scale: d.scale.abs(),
+                    material: d.material,
+                }
+            })
+            .collect();
+
+        let mut set = InstanceSet {
+            order: (0..instances.len()).collect(),
+            instances,
+            nodes: Vec::new(),
+        };
+        if !set.instances.is_empty() {
+            set.build_node(0, set.instances.len());
+        }
+        set
+    }
+
+    pub fn material(&self, index: usize) -> InstanceMaterial {
+        self.instances[index].material
+    }
+
+    pub fn normal_frame(&self, index: usize) -> [[f64; 3]; 3] {
+        self.instances[index].normal_frame
+    }
+
+    fn build_node(&mut self, start: usize, count: usize) -> usize {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        let mut c_min = [f64::MAX; 3];
+        let mut c_max = [f64::MIN; 3];
+        for &i in &self.order[start..start + count] {
+            let inst = &self.instances[i];
+            for k in 0..3 {
+                min[k] = min[k].min(inst.center[k] - inst.radius);
+                max[k] = max[k].max(inst.center[k] + inst.radius);
+                c_min[k] = c_min[k].min(inst.center[k]);
+                c_max[k] = c_max[k].max(inst.center[k]);
+            }
+        }
+
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode { min, max, start, count });
+        if count <= LEAF_SIZE {
+            return node;
+        }
+
+        // Median split along the longest axis of the centers
+        let extent = [c_max[0] - c_min[0], c_max[1] - c_min[1], c_max[2] - c_min[2]];
+        let axis = if extent[0] > extent[1] && extent[0] > extent[2] {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        };
+        let instances = &self.instances;
+        self.order[start..start + count].sort_by(|&a, &b| {
+            instances[a].center[axis]
+                .partial_cmp(&instances[b].center[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+
+        let half = count / 2;
+        self.build_node(start, half);
+        let right = self.build_node(start + half, count - half);
+        self.nodes[node].start = right;
+        self.nodes[node].count = 0;
+        node
+    }
+
+    // eval gets the instance index and the position in prototype space and returns the prototype distance with
+    // some payload. Returns the closest distance and the payload of that instance. Instances whose bounding sphere
+    // does not contain pos are not evaluated, their sphere distance is used instead. The payload belongs to the
+    // closest evaluated instance and is None if no instance was evaluated.
+    pub fn closest<T>(&self, pos: [f64; 3], eval: &dyn Fn(usize, [f64; 3]) -> (f64, T)) -> (f64, Option<T>) {
+        let mut best = (f64::MAX, None);
+        if !self.nodes.is_empty() {
+            self.query(0, pos, eval, &mut best);
+        }
+        (best.0, best.1.map(|(_, payload)| payload))
+    }
+
+    // best holds the closest distance so far and the closest evaluated distance with its payload
+    fn query<T>(
+        &self,
+        node: usize,
+        pos: [f64; 3],
+        eval: &dyn Fn(usize, [f64; 3]) -> (f64, T),
+        best: &mut (f64, Option<(f64, T)>),
+    ) {
+        let n = &self.nodes[node];
+        if n.count > 0 {
+            for &i in &self.order[n.start..n.start + n.count] {
+                let inst = &self.instances[i];
+                let offset = [pos[0] - inst.center[0], pos[1] - inst.center[1], pos[2] - inst.center[2]];
+                let sphere_dist = vecmath::vec3_len(offset) - inst.radius;
+                if sphere_dist >= best.0 {
+                    continue;
+                }
+                if sphere_dist > 0.0 {
+                    // Only a bound, the payload of a closer evaluated instance stays
+                    best.0 = sphere_dist;
+                    continue;
+                }
+                let (d, payload) = eval(i, helpers::mat_vec_mul(inst.mat_inv, pos));
+                let d = d * inst.scale;
+                if d < best.0 {
+                    best.0 = d;
+                }
+                if best.1.as_ref().is_none_or(|(hit, _)| d < *hit) {
+                    best.1 = Some((d, payload));
+                }
+            }
+            return;
+        }
+
+        let left = node + 1;
+        let right = n.start;
+        let d_left = self.box_distance(left, pos);
+        let d_right = self.box_distance(right, pos);
+        let (first, d_first, second, d_second) = if d_left < d_right {
+            (left, d_left, right, d_right)
+        } else {
+            (right, d_right, left, d_left)
+        };
+        if d_first < best.0 {
+            self.query(first, pos, eval, best);
+        }
+        if d_second < best.0 {
+            self.query(second, pos, eval, best);
+        }
+    }
+
+    fn box_distance(&self, node: usize, pos: [f64; 3]) -> f64 {
+        let n = &self.nodes[node];
+        let mut d2 = 0.0;
+        for ((min, max), p) in n.min.iter().zip(n.max).zip(pos) {
+            let d = (min - p).max(0.0).max(p - max);
+            d2 += d * d;
+        }
+        d2.sqrt()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn csv_skips_headers_and_fills_optional_columns() {
+        let csv = "x,y,z,rot_x\n# comment\n1, 2, 3\n4;5;6;90;0;0;2;0.5;0.6;0.7;8;0.1;0.2;0.3;1;0.4\n";
+        let instances = parse_csv(csv);
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].pos, [1.0, 2.0, 3.0]);
+        assert_eq!(instances[0].rot, [0.0, 0.0, 0.0]);
+        assert_eq!(instances[0].scale, 1.0);
+        assert!(instances[0].material.shading.is_none());
+        assert!(instances[0].material.color.is_none());
+        let m = instances[1].material;
+        assert_eq!(instances[1].rot, [90.0, 0.0, 0.0]);
+        assert_eq!(instances[1].scale, 2.0);
+        assert_eq!(m.shading, Some([0.5, 0.6, 0.7, 8.0]));
+        assert_eq!(m.color, Some([0.1, 0.2, 0.3]));
+        assert_eq!(m.metallic, Some(1.0));
+        assert_eq!(m.roughness, Some(0.4));
+    }
+
+    #[test]
+    fn ply_reads_vertex_properties_and_scales_uchar_colors() {
+        let ply = "ply\nformat ascii 1.0\nelement vertex 2\nproperty float x\nproperty float y\nproperty float z\n\
+                   property float scale\nproperty uchar red\nproperty uchar green\nproperty uchar blue\n\
+                   element face 0\nproperty list uchar int vertex_indices\nend_header\n\
+                   1 2 3 0.5 255 0 51\n-1 0 1 2 0 255 0\n";
+        let instances = parse_ply(ply);
+        assert_eq!(instances.len(), 2);
+        assert_eq!(instances[0].pos, [1.0, 2.0, 3.0]);
+        assert_eq!(instances[0].scale, 0.5);
+        assert_eq!(instances[0].material.color, Some([1.0, 0.0, 0.2]));
+        assert_eq!(instances[1].pos, [-1.0, 0.0, 1.0]);
+        assert!(instances[1].material.metallic.is_none());
+    }
+
+    #[test]
+    #[should_panic(expected = "Only ascii")]
+    fn ply_rejects_binary_files() {
+        parse_ply("ply\nformat binary_little_endian 1.0\nelement vertex 0\nend_header\n");
+    }
+}
@@ -1,3 +1,4 @@
+use crate::configuration;
 
 use std::{rc::Rc, cell::RefCell, f64::consts::PI};
 use vecmath;
@@ -55,4 +56,30 @@ impl PointLight{
     pub fn new(position: [f64!();3], color: [f64!();3], intensity: f64!()) -> PointLight{
         PointLight { position, color, intensity }
     }
-}
\ No newline at end of file
+}
+
+
+// Halo around surfaces a ray passed closely without hitting them. The glow fades from full color at the surface
+// to nothing at radius, falloff shapes the fade. Close passes late in the march are attenuated by their step, so
+// distant surfaces glow less than the ones the ray passed first.
+pub struct Glow {
+    color: [f64!();3],
+    radius: f64!(),
+    falloff: f64!(),
+}
+
+impl Glow{
+    pub fn new(color: [f64!();3], radius: f64!(), falloff: f64!()) -> Glow{
+        Glow { color, radius, falloff }
+    }
+
+    pub fn glow(&self, min_dist: f64, min_dist_step: u32) -> [f64;3]{
+        let radius = get_f64!(self.radius);
+        if radius <= 0.0 {
+            return [0.0, 0.0, 0.0];
+        }
+        let attenuation = 1.0 - (min_dist_step as f64 / configuration::step_number as f64).clamp(0.0, 1.0);
+        let strength = (1.0 - (min_dist / radius).clamp(0.0, 1.0)).powf(get_f64!(self.falloff)) * attenuation;
+        [get_f64!(self.color[0]) * strength, get_f64!(self.color[1]) * strength, get_f64!(self.color[2]) * strength]
+    }
+}
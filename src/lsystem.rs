@@ -0,0 +1,289 @@
+// L-systems for growing trees out of capsules. The turtle understands
+//   F, G  draw a segment          f  move without drawing
+//   + -   turn around up          & ^  pitch around left          \ /  roll around the heading
+//   |     turn around             [ ]  push and pop the state     !  shrink the radius
+// All other symbols are only used by the rules.
+use crate::primitive;
+use std::collections::HashMap;
+use std::{cell::RefCell, rc::Rc};
+use vecmath::{vec3_add, vec3_cross, vec3_dot, vec3_scale};
+
+pub struct LSystem {
+    pub axiom: String,
+    pub rules: HashMap<char, String>,
+}
+
+pub struct Segment {
+    pub a: [f64; 3],
+    pub b: [f64; 3],
+    pub radius: [f64; 2],
+    // Number of segments between the root and this segment, the growth reveals one segment per unit
+    pub order: f64,
+    // Index of the branch that drew this segment, every [ starts a new branch
+    pub branch: usize,
+}
+
+#[derive(Clone, Copy)]
+struct Turtle {
+    pos: [f64; 3],
+    heading: [f64; 3],
+    left: [f64; 3],
+    up: [f64; 3],
+    radius: f64,
+    order: f64,
+    branch: usize,
+}
+
+// Rodrigues rotation of v around the unit axis
+fn rotate(v: [f64; 3], axis: [f64; 3], angle: f64) -> [f64; 3] {
+    let (sin, cos) = angle.sin_cos();
+    let cross = vec3_cross(axis, v);
+    let dot = vec3_dot(axis, v);
+    vec3_add(
+        vec3_add(vec3_scale(v, cos), vec3_scale(cross, sin)),
+        vec3_scale(axis, dot * (1.0 - cos)),
+    )
+}
+
+impl LSystem {
+    pub fn new(axiom: String, rules: HashMap<char, String>) -> LSystem {
+        LSystem { axiom, rules }
+    }
+
+    pub fn expand(&self, iterations: u32) -> String {
+        let mut current = self.axiom.clone();
+        for _ in 0..iterations {
+            let mut next = String::with_capacity(current.len() * 2);
+            for c in current.chars() {
+                match self.rules.get(&c) {
+                    Some(replacement) => next.push_str(replacement),
+                    None => next.push(c),
+                }
+            }
+            current = next;
+        }
+        current
+    }
+
+    // The tree grows from the origin along +y. Also returns the parent of every branch, branch 0 is the trunk.
+    pub fn segments(
+        &self,
+        iterations: u32,
+        angle: f64,
+        length: f64,
+        radius: f64,
+        radius_decay: f64,
+    ) -> (Vec<Segment>, Vec<Option<usize>>) {
+        let angle = angle.to_radians();
+        let mut segments: Vec<Segment> = Vec::new();
+        let mut branches: Vec<Option<usize>> = vec![None];
+        let mut stack: Vec<Turtle> = Vec::new();
+        let mut turtle = Turtle {
+            pos: [0.0, 0.0, 0.0],
+            heading: [0.0, 1.0, 0.0],
+            left: [-1.0, 0.0, 0.0],
+            up: [0.0, 0.0, 1.0],
+            radius,
+            order: 0.0,
+            branch: 0,
+        };
+
+        for c in self.expand(iterations).chars() {
+            match c {
+                'F' | 'G' => {
+                    let end = vec3_add(turtle.pos, vec3_scale(turtle.heading, length));
+                    segments.push(Segment {
+                        a: turtle.pos,
+                        b: end,
+                        radius: [turtle.radius, turtle.radius],
+                        order: turtle.order,
+                        branch: turtle.branch,
+                    });
+                    turtle.pos = end;
+                    turtle.order += 1.0;
+                }
+                'f' => turtle.pos = vec3_add(turtle.pos, vec3_scale(turtle.heading, length)),
+                '+' | '-' | '|' => {
+                    let a = match c {
+                        '+' => angle,
+                        '-' => -angle,
+                        _ => std::f64::consts::PI,
+                    };
+                    turtle.heading = rotate(turtle.heading, turtle.up, a);
+                    turtle.left = rotate(turtle.left, turtle.up, a);
+                }
+                '&' | '^' => {
+                    let a = if c == '&' { angle } else { -angle };
+                    turtle.heading = rotate(turtle.heading, turtle.left, a);
+                    turtle.up = rotate(turtle.up, turtle.left, a);
+                }
+                '\\' | '/' => {
+                    let a = if c == '\\' { angle } else { -angle };
+                    turtle.left = rotate(turtle.left, turtle.heading, a);
+                    turtle.up = rotate(turtle.up, turtle.heading, a);
+                }
+                '[' => {
+                    stack.push(turtle);
+                    branches.push(Some(turtle.branch));
+                    turtle.branch = branches.len() - 1;
+                }
+                ']' => {
+                    if let Some(t) = stack.pop() {
+                        turtle = t;
+                    }
+                }
+                '!' => {
+                    // Tapers the segment that was just drawn towards the new radius
+                    turtle.radius *= radius_decay;
+                    if let Some(last) = segments.last_mut() {
+                        if last.b == turtle.pos {
+                            last.radius[1] = turtle.radius;
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        (segments, branches)
+    }
+
+    // Groups the segments by branch, None if nothing is drawn. The renderer and the scene export share this tree.
+    pub fn tree(
+        &self,
+        iterations: u32,
+        angle: f64,
+        length: f64,
+        radius: f64,
+        radius_decay: f64,
+    ) -> (Vec<Segment>, Option<BranchNode>) {
+        let (segments, branches) = self.segments(iterations, angle, length, radius, radius_decay);
+        let mut children: Vec<Vec<usize>> = vec![Vec::new(); branches.len()];
+        for (b, parent) in branches.iter().enumerate() {
+            if let Some(p) = parent {
+                children[*p].push(b);
+            }
+        }
+        let root = BranchNode::new(&segments, &children, 0);
+        (segments, root)
+    }
+
+    // One capsule per segment, growth is shared by all of them. Every branch is a smooth union of its capsules and
+    // sub branches with a bounding sphere, so rays far from a branch skip all of its capsules. Returns the children
+    // of the trunk.
+    #[allow(clippy::too_many_arguments)]
+    pub fn build(
+        &self,
+        iterations: u32,
+        angle: f64,
+        length: f64,
+        radius: f64,
+        radius_decay: f64,
+        smoothness: f64!(),
+        growth: f64!(),
+        material: primitive::Material,
+    ) -> Vec<Box<dyn primitive::Primitive>> {
+        let (segments, root) = self.tree(iterations, angle, length, radius, radius_decay);
+        let tree = Tree { segments: &segments, smoothness, growth, material };
+        root.map(|r| tree.prims(&r)).unwrap_or_default()
+    }
+}
+
+// A branch that draws anything, either through its own segments or through its sub branches
+pub struct BranchNode {
+    // Indices of the segments drawn by this branch
+    pub segments: Vec<usize>,
+    pub children: Vec<BranchNode>,
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+}
+
+impl BranchNode {
+    fn new(segments: &[Segment], children: &[Vec<usize>], index: usize) -> Option<BranchNode> {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        let own: Vec<usize> = (0..segments.len()).filter(|i| segments[*i].branch == index).collect();
+        for s in own.iter().map(|i| &segments[*i]) {
+            let r = s.radius[0].max(s.radius[1]);
+            for k in 0..3 {
+                min[k] = min[k].min(s.a[k].min(s.b[k]) - r);
+                max[k] = max[k].max(s.a[k].max(s.b[k]) + r);
+            }
+        }
+        let subs: Vec<BranchNode> =
+            children[index].iter().filter_map(|c| BranchNode::new(segments, children, *c)).collect();
+        for b in &subs {
+            for k in 0..3 {
+                min[k] = min[k].min(b.min[k]);
+                max[k] = max[k].max(b.max[k]);
+            }
+        }
+        if own.is_empty() && subs.is_empty() {
+            return None;
+        }
+        Some(BranchNode { segments: own, children: subs, min, max })
+    }
+
+    // Capsules and sub branches, a branch with a single member is replaced by that member instead of a group
+    pub fn member_count(&self) -> usize {
+        self.segments.len() + self.children.len()
+    }
+
+    // Bounding sphere of the capsules before blending, the group adds the margin of the current smoothness
+    pub fn bounds(&self) -> ([f64; 3], f64) {
+        let center = vec3_scale(vec3_add(self.min, self.max), 0.5);
+        (center, vecmath::vec3_len(vecmath::vec3_sub(self.max, center)))
+    }
+}
+
+type Prims = Vec<Box<dyn primitive::Primitive>>;
+
+struct Tree<'a> {
+    segments: &'a [Segment],
+    smoothness: f64!(),
+    growth: f64!(),
+    material: primitive::Material,
+}
+
+impl<'a> Tree<'a> {
+    // Capsules come before the groups of the sub branches, like the object and group members of an exported group
+    fn prims(&self, branch: &BranchNode) -> Prims {
+        let (mut capsules, groups) = self.members(branch);
+        capsules.extend(groups);
+        capsules
+    }
+
+    fn members(&self, branch: &BranchNode) -> (Prims, Prims) {
+        let mut capsules: Prims = Vec::new();
+        let mut groups: Prims = Vec::new();
+        for s in branch.segments.iter().map(|i| &self.segments[*i]) {
+            capsules.push(Box::new(primitive::Capsule::new(
+                f64v!(s.a),
+                f64v!(s.b),
+                [f64!(s.radius[0]), f64!(s.radius[1])],
+                self.growth.clone(),
+                s.order,
+                f64v!([0.0, 0.0, 0.0]),
+                f64v!([0.0, 0.0, 0.0]),
+                f64v!([1.0, 1.0, 1.0]),
+                Vec::new(),
+                Vec::new(),
+                Some(self.material.clone()),
+            )));
+        }
+        for child in &branch.children {
+            let (c, g) = self.members(child);
+            if child.member_count() == 1 {
+                capsules.extend(c);
+                groups.extend(g);
+            } else {
+                let (center, radius) = child.bounds();
+                let mut prims = c;
+                prims.extend(g);
+                let mut group = primitive::GroupPrimitive::new_union_smooth(prims, self.smoothness.clone());
+                group.set_bounds(center, radius);
+                groups.push(Box::new(group));
+            }
+        }
+        (capsules, groups)
+    }
+}
@@ -134,28 +134,42 @@ macro_rules! f64v {
 // D, O
 type Ray = ([f64; 3], [f64; 3]);
 type Sample = (f64, f64);
-// FilmSample, LensSample
-type CameraSample = (Sample, Sample);
+// FilmSample, LensSample, time inside the current shutter stratum from 0 to 1
+type CameraSample = (Sample, Sample, f64);
 mod configuration;
 // mod evaluator
+mod brdf;
 mod cameras;
+mod csg;
+mod curve;
+mod distance_grid;
+mod environment;
 mod evaluator;
 mod film;
 mod filter;
+mod heightfield;
 mod helpers;
+mod instancer;
 mod light;
+mod lsystem;
+mod mesh;
 mod modifier;
+mod noise;
 mod postprocessor;
 mod primitive;
 mod renderers;
 mod sampler;
 mod scene;
+mod script;
+mod sdf2d;
 mod shader;
+mod sky;
 mod solver;
+mod texture;
 mod transition;
 use primitive::Material;
 use renderers::Renderer;
-use scene::SceneObject;
+use scene::{SceneModifier, SceneObject};
 use std::time::{Duration, Instant};
 
 fn main() {
@@ -167,128 +181,507 @@ fn main() {
     }
 }
 
-fn add_prim(so: &SceneObject) -> Box<dyn primitive::Primitive> {
+// Noise modifiers come in blocks of three: Perlin, Simplex, Voronoi
+fn noise_type(index: i32) -> noise::NoiseType {
+    if index == 1 {
+        return noise::NoiseType::Simplex;
+    }
+    if index == 2 {
+        return noise::NoiseType::Voronoi;
+    }
+    noise::NoiseType::Perlin
+}
+
+fn add_pos_modifiers(modifiers: &[SceneModifier]) -> Vec<Box<dyn modifier::PosModifier>> {
+    let mut pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
+
+    for modifier in modifiers{
+        if modifier.modifier == 1 {
+            pos_modifier.push(Box::new(modifier::Distort::new(modifier.attribute3.to_owned(), [modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), modifier.attribute2.to_owned()], modifier.attribute4.to_owned())));
+        }else if modifier.modifier == 2 {
+            // attribute1 is the bounding radius used for the Lipschitz bound
+            pos_modifier.push(Box::new(modifier::Twist::new(modifier.attribute0.to_owned(), modifier.attribute1.to_owned())));
+        }else if modifier.modifier == 3 {
+            pos_modifier.push(Box::new(modifier::Bend::new(modifier.attribute0.to_owned(), modifier.attribute1.to_owned())));
+        }else if modifier.modifier == 4 {
+            pos_modifier.push(Box::new(modifier::Repetition::new(modifier.attribute0.to_owned())));
+        }else if modifier.modifier == 5 {
+            pos_modifier.push(Box::new(modifier::RepetitionLimited::new(modifier.attribute0.to_owned(), [modifier.attribute1.to_owned(), modifier.attribute2.to_owned(), modifier.attribute3.to_owned()])));
+        }else if modifier.modifier >= 9 && modifier.modifier <= 11 {
+            let noise = noise::Noise::new(modifier.seed, noise_type(modifier.modifier - 9));
+            pos_modifier.push(Box::new(modifier::NoiseDistort::new(noise, modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), modifier.attribute2.to_owned(), modifier.attribute3.to_owned(), modifier.attribute4.to_owned())));
+        }else if modifier.modifier == 15 {
+            // attribute0 is a bitmask of the mirrored axes: 1 = x, 2 = y, 4 = z
+            let axes = get_ref_f64!(modifier.attribute0) as i32;
+            pos_modifier.push(Box::new(modifier::Mirror::new([axes & 1 != 0, axes & 2 != 0, axes & 4 != 0], [modifier.attribute1.to_owned(), modifier.attribute2.to_owned(), modifier.attribute3.to_owned()])));
+        }else if modifier.modifier == 16 {
+            let axis = (get_ref_f64!(modifier.attribute1) as usize).min(2);
+            pos_modifier.push(Box::new(modifier::PolarRepetition::new(axis, modifier.attribute0.to_owned(), modifier.attribute2.to_owned())));
+        }else if modifier.modifier == 17 {
+            pos_modifier.push(Box::new(modifier::Kaleidoscope::new(modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), [modifier.attribute2.to_owned(), modifier.attribute3.to_owned(), modifier.attribute4.to_owned()])));
+        }else if modifier.modifier == 18 {
+            // The attributes are available to the script through param(i)
+            let params = vec![modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), modifier.attribute2.to_owned(), modifier.attribute3.to_owned(), modifier.attribute4.to_owned()];
+            pos_modifier.push(Box::new(modifier::ScriptModifier::new(modifier.path.as_deref().expect("Script modifiers need a path"), params)));
+        }
+    }
+    pos_modifier
+}
+
+fn add_dist_modifiers(modifiers: &[SceneModifier]) -> Vec<Box<dyn modifier::DistModifier>> {
+    let mut dist_modifier = Vec::<Box<dyn modifier::DistModifier>>::new();
+
+    for modifier in modifiers{
+        if modifier.modifier == 6 {
+            dist_modifier.push(Box::new(modifier::Round::new(modifier.attribute0.to_owned())));
+        }else if modifier.modifier == 7 {
+            dist_modifier.push(Box::new(modifier::Onion::new(modifier.attribute0.to_owned())));
+        }else if modifier.modifier == 8 {
+            dist_modifier.push(Box::new(modifier::Displacement::new(modifier.attribute3.to_owned(), [modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), modifier.attribute2.to_owned()], modifier.attribute4.to_owned())));
+        }else if modifier.modifier >= 12 && modifier.modifier <= 14 {
+            let noise = noise::Noise::new(modifier.seed, noise_type(modifier.modifier - 12));
+            dist_modifier.push(Box::new(modifier::NoiseDisplacement::new(noise, modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), modifier.attribute2.to_owned(), modifier.attribute3.to_owned(), modifier.attribute4.to_owned())));
+        }
+    }
+    dist_modifier
+}
+
+// values[0] selects the shape, its parameters start at values[3]
+fn add_shape(so: &SceneObject) -> Box<dyn sdf2d::Shape2D> {
+    let shape = get_ref_f64!(so.values[0]).round() as i32;
+    if shape == 1 {
+        return Box::new(sdf2d::Rectangle::new([so.values[3].to_owned(), so.values[4].to_owned()]));
+    }
+    if shape == 2 {
+        return Box::new(sdf2d::Polygon::new(
+            so.points.iter().map(|p| [p[0].to_owned(), p[1].to_owned()]).collect(),
+        ));
+    }
+    if shape == 3 {
+        return Box::new(sdf2d::Star::new(so.values[3].to_owned(), so.values[4].to_owned(), so.values[5].to_owned()));
+    }
+    if shape == 4 {
+        return Box::new(sdf2d::Arc::new(so.values[3].to_owned(), so.values[4].to_owned(), so.values[5].to_owned()));
+    }
+    if shape == 5 {
+        return Box::new(sdf2d::Segment::new(
+            [so.values[3].to_owned(), so.values[4].to_owned()],
+            [so.values[5].to_owned(), so.values[6].to_owned()],
+            so.values[7].to_owned(),
+        ));
+    }
+    Box::new(sdf2d::Circle::new(so.values[3].to_owned()))
+}
+
+fn add_prim(objects: &[SceneObject], index: usize) -> Box<dyn primitive::Primitive> {
+    let so = &objects[index];
+    let mut prim = if so.prim_type == 13 {
+        add_instancer(objects, so)
+    } else if so.prim_type == 7 {
+        add_baked(objects, so)
+    } else {
+        create_prim(so)
+    };
+    if let Some(bound) = &so.lipschitz {
+        prim.set_lipschitz_override(bound.to_owned());
+    }
+    if let Some(parent) = so.parent {
+        prim.set_parent(add_transform(objects, parent as usize, 0));
+    }
+    prim
+}
+
+// Builds a group modifier with its nested groups, the objects it uses are added to used. None if it has no members.
+fn add_group(scene: &scene::Scene, index: usize, used: &mut Vec<i32>) -> Option<Box<dyn primitive::Primitive>> {
+    let go = &scene.group_modifiers[index];
+    let mut members: Vec<i32> = Vec::new();
+    if go.prim0 != -1 && go.prim1 != -1 {
+        members.extend([go.prim0, go.prim1, go.prim2, go.prim3].iter().filter(|p| **p != -1));
+    }
+    members.extend(&go.objects);
+    let mut cur_prims: Vec<Box<dyn primitive::Primitive>> = Vec::new();
+    for p in members {
+        used.push(p);
+        cur_prims.push(add_prim(&scene.objects, p as usize));
+    }
+    for g in &go.groups {
+        // Groups may only contain earlier groups, so nesting can not form a cycle
+        if *g < 0 || *g as usize >= index {
+            panic!("Group {} can only contain groups listed before it, got {}", index, g);
+        }
+        if let Some(prim) = add_group(scene, *g as usize, used) {
+            cur_prims.push(prim);
+        }
+    }
+    if cur_prims.is_empty() {
+        return None;
+    }
+    let group_type = primitive::GroupModifierType::from_code(go.modifier)?;
+    let mut group = primitive::GroupPrimitive::new(
+        group_type,
+        cur_prims,
+        go.prim_attribute.to_owned(),
+        go.prim_attribute2.to_owned(),
+        go.position.to_owned(),
+        go.rotation.to_owned(),
+        go.scale.to_owned(),
+        add_pos_modifiers(&go.modifiers),
+        add_dist_modifiers(&go.modifiers),
+    );
+    if let Some((center, radius)) = go.bounds {
+        group.set_bounds(center, radius);
+    }
+    let mut group: Box<dyn primitive::Primitive> = Box::new(group);
+    if let Some(bound) = &go.lipschitz {
+        group.set_lipschitz_override(bound.to_owned());
+    }
+    Some(group)
+}
+
+fn scene_material(so: &SceneObject) -> Material {
     let mut m = Material::new_base();
     m.albedo = f64!(1.0);
     m.specular = f64!(0.8);
     m.diffuse = f64!(0.2);
     m.n_specular = f64!(10.0);
+    if let Some(sm) = &so.material {
+        m.color = [sm.color[0].to_owned(), sm.color[1].to_owned(), sm.color[2].to_owned()];
+        m.metallic = sm.metallic.to_owned();
+        m.roughness = sm.roughness.to_owned();
+        m.emission = [sm.emission[0].to_owned(), sm.emission[1].to_owned(), sm.emission[2].to_owned()];
+        m.emission_strength = sm.emission_strength.to_owned();
+        m.texture = sm.texture;
+    }
+    m
+}
 
-    if so.prim_type == 1 {
-        let mut pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
-        
-        for modifier in &so.modifiers{
-            if(modifier.modifier == 1){
-                pos_modifier.push(Box::new(modifier::Distort::new(modifier.attribute3.to_owned(), [modifier.attribute0.to_owned(), modifier.attribute1.to_owned(), modifier.attribute2.to_owned()], modifier.attribute4.to_owned())));
-            }else if(modifier.modifier == 2){
-                pos_modifier.push(Box::new(modifier::Twist::new(modifier.attribute0.to_owned())));
-            }else if(modifier.modifier == 3){
-                pos_modifier.push(Box::new(modifier::Bend::new(modifier.attribute0.to_owned())));
-            }else if(modifier.modifier == 4){
-                pos_modifier.push(Box::new(modifier::Repetition::new(modifier.attribute0.to_owned())));
-            }else if(modifier.modifier == 5){
-                pos_modifier.push(Box::new(modifier::RepetitionLimited::new(modifier.attribute0.to_owned(), [modifier.attribute1.to_owned(), modifier.attribute2.to_owned(), modifier.attribute3.to_owned()])));
-            }
-            // else if(modifier.modifier == 6){
-            //     pos_modifier.push(Box::new(modifier::Round::new(modifier.attribute0.to_owned())));
-            // }
+// values[0] is the index of the prototype object, values[1] its bounding radius. The instances are read from the
+// object's path and its instance list.
+fn add_instancer(objects: &[SceneObject], so: &SceneObject) -> Box<dyn primitive::Primitive> {
+    let (position, rotation, scale) = convert_transform(so);
+    let m = scene_material(so);
+
+    let prototype = get_ref_f64!(so.values[0]) as usize;
+    if prototype >= objects.len() || objects[prototype].prim_type == 13 {
+        panic!("Instancer prototype {} is not a valid object", prototype);
+    }
+    let mut instances = match &so.path {
+        Some(path) => instancer::load_instances(path),
+        None => Vec::new(),
+    };
+    instances.extend_from_slice(&so.instances);
+
+    let pos_modifier = add_pos_modifiers(&so.modifiers);
+    Box::new(primitive::Instancer::new(
+        add_prim(objects, prototype),
+        get_ref_f64!(so.values[1]),
+        &instances,
+        position,
+        rotation,
+        scale,
+        pos_modifier,
+        add_dist_modifiers(&so.modifiers),
+        Some(m),
+    ))
+}
+
+type AnimatedVec3 = [f64!(); 3];
+
+// Position, rotation and scale of an object
+fn convert_transform(so: &SceneObject) -> (AnimatedVec3, AnimatedVec3, AnimatedVec3) {
+    (so.position.clone(), so.rotation.clone(), so.scale.clone())
+}
+
+// Builds the transformation chain of an object and all of its parents
+fn add_transform(objects: &[SceneObject], index: usize, depth: usize) -> Rc<primitive::Transform> {
+    if depth > objects.len() {
+        panic!("The parents of object {} form a cycle", index);
+    }
+    let so = &objects[index];
+    let parent = so.parent.map(|p| add_transform(objects, p as usize, depth + 1));
+    let (position, rotation, scale) = convert_transform(so);
+    Rc::new(primitive::Transform::new(
+        position,
+        rotation,
+        scale,
+        parent,
+    ))
+}
+
+// values[0] selects tricubic sampling, values[1] is the index of the object the grid was baked from or -1. The grid
+// is sampled in the source's world space, so the source keeps its own transform and is only evaluated exactly
+// close to the surface.
+fn add_baked(objects: &[SceneObject], so: &SceneObject) -> Box<dyn primitive::Primitive> {
+    let (position, rotation, scale) = convert_transform(so);
+    let m = scene_material(so);
+
+    let interpolation = if get_ref_f64!(so.values[0]) > 0.5 {
+        distance_grid::Interpolation::Tricubic
+    } else {
+        distance_grid::Interpolation::Trilinear
+    };
+    let source_index = get_ref_f64!(so.values[1]);
+    let source = if source_index < 0.0 {
+        None
+    } else {
+        let source_index = source_index as usize;
+        if source_index >= objects.len() || objects[source_index].prim_type == 7 {
+            panic!("Baked grid source {} is not a valid object", source_index);
         }
+        Some(add_prim(objects, source_index))
+    };
+
+    let pos_modifier = add_pos_modifiers(&so.modifiers);
+    Box::new(primitive::BakedPrimitive::new(
+        distance_grid::DistanceGrid::load(so.path.as_deref().expect("Baked grid objects need a path")),
+        interpolation,
+        source,
+        position,
+        rotation,
+        scale,
+        pos_modifier,
+        add_dist_modifiers(&so.modifiers),
+        Some(m),
+    ))
+}
+
+// Bakes the object selected by the scene's bake section in its world space, the file can be loaded by baked grid
+// objects with this object as source
+fn bake_scene(scene: &scene::Scene) -> bool {
+    let bake = match &scene.bake {
+        Some(b) => b,
+        None => return false,
+    };
+    if bake.object >= scene.objects.len() {
+        panic!("Bake object {} is not a valid object", bake.object);
+    }
+    let t = configuration::still_frame as f64 / configuration::ups;
+    evaluator::evaluate(t);
+    let mut prim = add_prim(&scene.objects, bake.object);
+    prim.evaluate(t);
+
+    let start = Instant::now();
+    let grid = distance_grid::DistanceGrid::bake(prim.as_ref(), bake.min, bake.max, bake.cell_size, bake.sparse);
+    grid.save(&bake.path);
+    println!("Baked object {} to {} in {}", bake.object, bake.path, start.elapsed().as_millis() as f64 * 0.001);
+    true
+}
+
+fn export_scene(scene: &scene::Scene) -> bool {
+    let export = match &scene.export {
+        Some(e) => e,
+        None => return false,
+    };
+    std::fs::write(&export.path, &export.contents).expect("Something went wrong writing the L-system export");
+    println!("Exported L-system to {}", export.path);
+    true
+}
+
+fn create_prim(so: &SceneObject) -> Box<dyn primitive::Primitive> {
+    let (position, rotation, scale) = convert_transform(so);
+    let m = scene_material(so);
 
+    if so.prim_type == 1 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
         return Box::new(primitive::Sphere::new(
             so.values[0].to_owned(),
-            [
-                so.position[0].to_owned(),
-                so.position[1].to_owned(),
-                so.position[2].to_owned(),
-            ],
-            [
-                so.rotation[0].to_owned(),
-                so.rotation[1].to_owned(),
-                so.rotation[2].to_owned(),
-            ],
-            [
-                so.scale[0].to_owned(),
-                so.scale[1].to_owned(),
-                so.scale[2].to_owned(),
-            ],
+            position,
+            rotation,
+            scale,
             pos_modifier,
+            add_dist_modifiers(&so.modifiers),
             Some(m),
         ));
     }
     if so.prim_type == 2 {
-        let pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
         return Box::new(primitive::Torus::new(
             so.values[0].to_owned(),
             so.values[1].to_owned(),
-            [
-                so.position[0].to_owned(),
-                so.position[1].to_owned(),
-                so.position[2].to_owned(),
-            ],
-            [
-                so.rotation[0].to_owned(),
-                so.rotation[1].to_owned(),
-                so.rotation[2].to_owned(),
-            ],
-            [
-                so.scale[0].to_owned(),
-                so.scale[1].to_owned(),
-                so.scale[2].to_owned(),
-            ],
+            position,
+            rotation,
+            scale,
             pos_modifier,
+            add_dist_modifiers(&so.modifiers),
             Some(m),
         ));
     }
     if so.prim_type == 3 {
-        let pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
         return Box::new(primitive::Cube::new(
             [
                 so.values[0].to_owned(),
                 so.values[1].to_owned(),
                 so.values[2].to_owned(),
             ],
-            [
-                so.position[0].to_owned(),
-                so.position[1].to_owned(),
-                so.position[2].to_owned(),
-            ],
-            [
-                so.rotation[0].to_owned(),
-                so.rotation[1].to_owned(),
-                so.rotation[2].to_owned(),
-            ],
-            [
-                so.scale[0].to_owned(),
-                so.scale[1].to_owned(),
-                so.scale[2].to_owned(),
-            ],
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 6 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        return Box::new(primitive::Mesh::new(
+            so.path.as_deref().expect("Mesh objects need a path"),
+            position,
+            rotation,
+            scale,
             pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 8 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        // Without a height map path the terrain is fBm with values[3] frequency, values[4] octaves, values[5] lacunarity
+        let source = match &so.path {
+            Some(path) => heightfield::HeightSource::load_image(path),
+            None => heightfield::HeightSource::new_fbm(
+                configuration::noise_seed,
+                get_ref_f64!(so.values[3]),
+                get_ref_f64!(so.values[4]).round().max(1.0) as u32,
+                get_ref_f64!(so.values[5]),
+            ),
+        };
+        return Box::new(primitive::Heightfield::new(
+            source,
+            so.values[0].to_owned(),
+            [so.values[1].to_owned(), so.values[2].to_owned()],
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 9 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        // values[3] selects a Catmull-Rom spline instead of cubic Bezier spans
+        let curve_type = if get_ref_f64!(so.values[3]) > 0.5 {
+            curve::CurveType::CatmullRom
+        } else {
+            curve::CurveType::Bezier
+        };
+        return Box::new(primitive::Tube::new(
+            so.points.iter().map(|p| [p[0].to_owned(), p[1].to_owned(), p[2].to_owned()]).collect(),
+            curve_type,
+            [so.values[0].to_owned(), so.values[1].to_owned()],
+            so.values[2].to_owned(),
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 10 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        return Box::new(primitive::Extrusion::new(
+            add_shape(so),
+            get_ref_f64!(so.values[1]) as usize,
+            so.values[2].to_owned(),
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 11 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        return Box::new(primitive::Revolution::new(
+            add_shape(so),
+            get_ref_f64!(so.values[1]) as usize,
+            so.values[2].to_owned(),
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 15 {
+        // points[0] and points[1] are the end points, values: radius at both ends, growth, growth start
+        if so.points.len() < 2 {
+            panic!("Capsule objects need two points");
+        }
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        return Box::new(primitive::Capsule::new(
+            [so.points[0][0].to_owned(), so.points[0][1].to_owned(), so.points[0][2].to_owned()],
+            [so.points[1][0].to_owned(), so.points[1][1].to_owned(), so.points[1][2].to_owned()],
+            [so.values[0].to_owned(), so.values[1].to_owned()],
+            so.values[2].to_owned(),
+            get_ref_f64!(so.values[3]),
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+            Some(m),
+        ));
+    }
+    if so.prim_type == 14 {
+        // values: angle, iterations, segment length, radius, radius decay, smoothness, growth in segments
+        let lsystem = so.lsystem.as_ref().expect("L-system objects need an lsystem");
+        let capsules = lsystem.build(
+            get_ref_f64!(so.values[1]).max(0.0) as u32,
+            get_ref_f64!(so.values[0]),
+            get_ref_f64!(so.values[2]),
+            get_ref_f64!(so.values[3]),
+            get_ref_f64!(so.values[4]),
+            so.values[5].to_owned(),
+            so.values[6].to_owned(),
+            m,
+        );
+        if capsules.is_empty() {
+            panic!("L-system does not draw any segments");
+        }
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        return Box::new(primitive::GroupPrimitive::new(
+            primitive::GroupModifierType::SmoothUnion,
+            capsules,
+            so.values[5].to_owned(),
+            f64!(0.0),
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
+        ));
+    }
+    if so.prim_type == 12 {
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
+        // All values are available to the script through param(i)
+        return Box::new(primitive::ScriptedPrimitive::new(
+            so.path.as_deref().expect("Scripted objects need a path"),
+            so.values.to_vec(),
+            position,
+            rotation,
+            scale,
+            pos_modifier,
+            add_dist_modifiers(&so.modifiers),
             Some(m),
         ));
     }
     if so.prim_type == 4 {
-        let pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
         return Box::new(primitive::Mandelbulb::new(
             so.values[0].to_owned(),
-            [
-                so.position[0].to_owned(),
-                so.position[1].to_owned(),
-                so.position[2].to_owned(),
-            ],
-            [
-                so.rotation[0].to_owned(),
-                so.rotation[1].to_owned(),
-                so.rotation[2].to_owned(),
-            ],
-            [
-                so.scale[0].to_owned(),
-                so.scale[1].to_owned(),
-                so.scale[2].to_owned(),
-            ],
+            position,
+            rotation,
+            scale,
             pos_modifier,
+            add_dist_modifiers(&so.modifiers),
         ));
     } else {
-        let pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
+        let pos_modifier = add_pos_modifiers(&so.modifiers);
         return Box::new(primitive::Julia::new(
             [
                 so.values[0].to_owned(),
@@ -296,27 +689,24 @@ fn add_prim(so: &SceneObject) -> Box<dyn primitive::Primitive> {
                 so.values[2].to_owned(),
                 so.values[3].to_owned(),
             ],
-            [
-                so.position[0].to_owned(),
-                so.position[1].to_owned(),
-                so.position[2].to_owned(),
-            ],
-            [
-                so.rotation[0].to_owned(),
-                so.rotation[1].to_owned(),
-                so.rotation[2].to_owned(),
-            ],
-            [
-                so.scale[0].to_owned(),
-                so.scale[1].to_owned(),
-                so.scale[2].to_owned(),
-            ],
+            position,
+            rotation,
+            scale,
             pos_modifier,
+            add_dist_modifiers(&so.modifiers),
         ));
     }
 }
 
 fn render_scene() {
+    if configuration::render_scene {
+        let scene = scene::load_scene("test3.rma.json");
+        let baked = bake_scene(&scene);
+        let exported = export_scene(&scene);
+        if baked || exported {
+            return;
+        }
+    }
     if configuration::video {
         let mut frames: Vec<Vec<u32>> = Vec::new();
         for _t in 0..configuration::threads {
@@ -373,49 +763,22 @@ fn render_frames(frames: Vec<u32>, file_name: &str) {
 
     let mut group_modifier_indices: Vec<i32> = Vec::new();
 
-    for go in scene.group_modifiers {
-        if go.prim0 != -1 && go.prim1 != -1 {
-            let mut cur_prims: Vec<Box<dyn primitive::Primitive>> = Vec::new();
-            group_modifier_indices.push(go.prim0);
-            cur_prims.push(add_prim(&scene.objects[go.prim0 as usize]));
-            group_modifier_indices.push(go.prim1);
-            cur_prims.push(add_prim(&scene.objects[go.prim1 as usize]));
-            if go.prim2 != -1 {
-                group_modifier_indices.push(go.prim2);
-                cur_prims.push(add_prim(&scene.objects[go.prim2 as usize]));
-            }
-            if go.prim3 != -1 {
-                group_modifier_indices.push(go.prim3);
-                cur_prims.push(add_prim(&scene.objects[go.prim3 as usize]));
-            }
-            if go.modifier == 1 {
-                primitives.push(Box::new(primitive::GroupPrimitive::new_union(cur_prims)));
-            } else if go.modifier == 2 {
-                primitives.push(Box::new(primitive::GroupPrimitive::new_subtraction(
-                    cur_prims,
-                )));
-            } else if go.modifier == 3 {
-                primitives.push(Box::new(primitive::GroupPrimitive::new_intersection(
-                    cur_prims,
-                )));
-            } else if go.modifier == 4 {
-                primitives.push(Box::new(primitive::GroupPrimitive::new_union_smooth(
-                    cur_prims,
-                    go.prim_attribute,
-                )));
-            } else if go.modifier == 5 {
-                primitives.push(Box::new(primitive::GroupPrimitive::new_subtraction_smooth(
-                    cur_prims,
-                    go.prim_attribute,
-                )));
-            } else if go.modifier == 6 {
-                primitives.push(Box::new(
-                    primitive::GroupPrimitive::new_intersection_smooth(
-                        cur_prims,
-                        go.prim_attribute,
-                    ),
-                ));
-            }
+    // Nested groups are only rendered through the group that contains them
+    let nested: Vec<i32> = scene.group_modifiers.iter().flat_map(|g| g.groups.iter().copied()).collect();
+    for g in 0..scene.group_modifiers.len() {
+        if nested.contains(&(g as i32)) {
+            continue;
+        }
+        if let Some(group) = add_group(&scene, g, &mut group_modifier_indices) {
+            primitives.push(group);
+        }
+    }
+    // Prototypes are only rendered through their instancer, bake sources through their baked grid
+    for so in &scene.objects {
+        if so.prim_type == 13 {
+            group_modifier_indices.push(get_ref_f64!(so.values[0]) as i32);
+        } else if so.prim_type == 7 && get_ref_f64!(so.values[1]) >= 0.0 {
+            group_modifier_indices.push(get_ref_f64!(so.values[1]) as i32);
         }
     }
     for so in 0..scene.objects.len() {
@@ -428,7 +791,7 @@ fn render_frames(frames: Vec<u32>, file_name: &str) {
         }
         if !found {
             let pos_modifier = Vec::<Box<dyn modifier::PosModifier>>::new();
-            primitives.push(add_prim(&scene.objects[so]));
+            primitives.push(add_prim(&scene.objects, so));
         }
     }
 
@@ -445,26 +808,66 @@ fn render_frames(frames: Vec<u32>, file_name: &str) {
         ],
     );
 
+    let sky = scene.sky.as_ref().map(|s| {
+        sky::PreethamSky::new(
+            [s.sun[0].to_owned(), s.sun[1].to_owned(), s.sun[2].to_owned()],
+            s.turbidity.to_owned(),
+            s.intensity.to_owned(),
+        )
+    });
+
     let mut lights = Vec::<Box<dyn light::Light>>::new();
     let mut lights_solver = Vec::<Box<dyn light::Light>>::new();
-    lights.push(Box::new(light::DirectionalLight::new(
-        f64v!(vecmath::vec3_normalized([0.2, -1.0, 0.0])),
-        f64v!([1.0, 1.0, 1.0]),
-        f64!(1.5),
-    )));
-    lights_solver.push(Box::new(light::DirectionalLight::new(
-        f64v!(vecmath::vec3_normalized([0.2, -1.0, 0.0])),
-        f64v!([1.0, 1.0, 1.0]),
-        f64!(1.5),
-    )));
+    match (&sky, &scene.sky) {
+        (Some(sky), Some(s)) => {
+            lights.push(Box::new(sky.sun_light(s.sun_intensity.to_owned())));
+            lights_solver.push(Box::new(sky.sun_light(s.sun_intensity.to_owned())));
+        }
+        _ => {
+            lights.push(Box::new(light::DirectionalLight::new(
+                f64v!(vecmath::vec3_normalized([0.2, -1.0, 0.0])),
+                f64v!([1.0, 1.0, 1.0]),
+                f64!(1.5),
+            )));
+            lights_solver.push(Box::new(light::DirectionalLight::new(
+                f64v!(vecmath::vec3_normalized([0.2, -1.0, 0.0])),
+                f64v!([1.0, 1.0, 1.0]),
+                f64!(1.5),
+            )));
+        }
+    }
 
 
     let noise = postprocessor::NoisePostProcessor::new(f64!(0.02), f64!(0.01));
     let film = film::BasicFilm::new(vec![], vec![Box::new(noise)]);
     let solver = solver::VolumetricSolver::new(primitives, lights_solver);
-    let bg_shader = shader::BackgroundLinearYGradient::new([0.05, 0.02, 0.04], [0.1, 0.06, 0.06]);
+    let environment = scene
+        .environment
+        .as_ref()
+        .map(|e| environment::EnvironmentMap::load(&e.path, e.rotation.to_owned(), e.intensity.to_owned()));
+    // The environment map is preferred over the sky, scenes without either keep their gradient background
+    let bg_shader: Box<dyn shader::BackgroundShader> = match (&environment, sky) {
+        (Some(e), _) => Box::new(e.clone()),
+        (None, Some(sky)) => Box::new(sky),
+        (None, None) => Box::new(shader::BackgroundLinearYGradient::new([0.05, 0.02, 0.04], [0.1, 0.06, 0.06])),
+    };
     // let shader = shader::NormalShader::new(Box::new(bg_shader));
-    let shader = shader::PhongShader::new(Box::new(bg_shader));
+    let shader: Box<dyn shader::Shader> = match &scene.shader {
+        Some(s) => match (&s.palette, &s.path) {
+            (Some(p), _) => Box::new(shader::PaletteShader::new(
+                p.palette.clone(),
+                p.channel,
+                p.frequency.to_owned(),
+                p.offset.to_owned(),
+                p.cycle_speed.to_owned(),
+                s.lit,
+                bg_shader,
+            )),
+            (None, Some(path)) => Box::new(shader::ScriptShader::new(path, s.lit, bg_shader)),
+            (None, None) => panic!("Scene shader needs a path or a palette"),
+        },
+        None => Box::new(shader::PbrShader::new(bg_shader)),
+    };
     // let shader = shader::FractalShader::new(
     //     f64v!([0.1, 0.1, 0.4]),
     //     f64v!([0.2, 0.9, 0.8]),
@@ -472,8 +875,15 @@ fn render_frames(frames: Vec<u32>, file_name: &str) {
     //     [f64!(0.0), f64!(-45.0), f64!(-45.0)],
     //     Box::new(bg_shader),
     // );
-    let sampler = sampler::JitterSampler::new(0.5);
-
+    let mut sampler = sampler::JitterSampler::new(0.5);
+    sampler.set_shutter(scene.shutter.open, scene.shutter.close, scene.shutter.time_samples);
+    let glow = scene.glow.as_ref().map(|g| {
+        light::Glow::new(
+            [g.color[0].to_owned(), g.color[1].to_owned(), g.color[2].to_owned()],
+            g.radius.to_owned(),
+            g.falloff.to_owned(),
+        )
+    });
     let mut renderer = renderers::LightRenderer::new(
         camera,
         film,
@@ -482,18 +892,18 @@ fn render_frames(frames: Vec<u32>, file_name: &str) {
         sampler,
         lights,
         f64v!([0.001, 0.001, 0.001]),
+        glow,
+        environment,
     );
+    renderer.set_debug_overshoot(scene.debug_overshoot);
 
-    // let mut renderer = renderers::SolverRenderer::new(camera, film, solver, shader, sampler);
+    // let mut renderer = renderers::SolverRenderer::new(camera, film, solver, shader, sampler, None);
     //let mut renderer= renderers::CameraRayRenderer::new(camera, film);
 
     for i in frames {
         let t = i as f64 / configuration::ups;
 
-        evaluator::evaluate(t);
-        renderer.prepare_render();
-        renderer.evaluate(t);
-        renderer.render();
+        renderer.render_frame(t);
 
         let path = if file_name == "" {
             format!("results/{}.png", i)
@@ -623,6 +1033,7 @@ fn render_frames_code(frames: Vec<u32>, file_name: &str) {
             mandel_scale_ref,
         ],
         pos_modifier,
+        Vec::new(),
     );
     primitives.push(
         // Box::new(primitive::Box::new([1.0,1.0,1.0], [0.0, 0.0, -10.0], [0.0, 0.0, 0.0], [1.0, 1.0, 1.0]))
@@ -744,14 +1155,11 @@ fn render_frames_code(frames: Vec<u32>, file_name: &str) {
     );
     let sampler = sampler::JitterSampler::new(0.5);
     //let mut renderer= renderers::CameraRayRenderer::new(camera, film);
-    let mut renderer = renderers::SolverRenderer::new(camera, film, solver, shader, sampler);
+    let mut renderer = renderers::SolverRenderer::new(camera, film, solver, shader, sampler, None);
     for i in frames {
         let t = i as f64 / configuration::ups;
 
-        evaluator::evaluate(t);
-        renderer.prepare_render();
-        renderer.evaluate(t);
-        renderer.render();
+        renderer.render_frame(t);
 
         let path = if file_name == "" {
             format!("results/{}.png", i)
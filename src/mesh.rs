@@ -0,0 +1,431 @@
+// Triangle meshes for the mesh primitive. Signed distances use the angle weighted pseudo-normals
+// from Baerentzen and Aanaes, "Signed distance computation using the angle weighted pseudonormal".
+use std::collections::HashMap;
+use std::fs;
+use vecmath::{vec3_add, vec3_cross, vec3_dot, vec3_normalized, vec3_scale, vec3_sub};
+
+const LEAF_SIZE: usize = 4;
+
+// The part of the triangle the closest point lies on, edges are ab = 0, bc = 1, ca = 2
+enum Feature {
+    Face,
+    Edge(usize),
+    Vertex(usize),
+}
+
+struct Triangle {
+    indices: [usize; 3],
+    face_normal: [f64; 3],
+    edge_normals: [[f64; 3]; 3],
+}
+
+struct BvhNode {
+    min: [f64; 3],
+    max: [f64; 3],
+    // Leafs store a range of `order`, inner nodes the index of their second child. The first child follows directly.
+    start: usize,
+    count: usize,
+}
+
+pub struct TriangleMesh {
+    vertices: Vec<[f64; 3]>,
+    vertex_normals: Vec<[f64; 3]>,
+    triangles: Vec<Triangle>,
+    order: Vec<usize>,
+    nodes: Vec<BvhNode>,
+}
+
+pub fn load_mesh(path: &str) -> TriangleMesh {
+    let lower = path.to_lowercase();
+    let (vertices, faces) = if lower.ends_with(".stl") {
+        let bytes = fs::read(path).expect("Something went wrong reading the mesh file");
+        parse_stl(&bytes)
+    } else {
+        let contents = fs::read_to_string(path).expect("Something went wrong reading the mesh file");
+        parse_obj(&contents)
+    };
+    TriangleMesh::new(vertices, faces)
+}
+
+fn parse_obj(contents: &str) -> (Vec<[f64; 3]>, Vec<[usize; 3]>) {
+    let mut vertices: Vec<[f64; 3]> = Vec::new();
+    let mut faces: Vec<[usize; 3]> = Vec::new();
+
+    for line in contents.lines() {
+        let mut parts = line.split_whitespace();
+        match parts.next() {
+            Some("v") => {
+                let mut v = [0.0; 3];
+                for c in v.iter_mut() {
+                    *c = parts
+                        .next()
+                        .and_then(|x| x.parse::<f64>().ok())
+                        .expect("OBJ vertex is not well-formatted");
+                }
+                vertices.push(v);
+            }
+            Some("f") => {
+                // Only the position index of "v/vt/vn" is used, negative indices count from the end
+                let polygon: Vec<usize> = parts
+                    .map(|x| {
+                        let index = x
+                            .split('/')
+                            .next()
+                            .and_then(|i| i.parse::<i64>().ok())
+                            .expect("OBJ face is not well-formatted");
+                        let len = vertices.len() as i64;
+                        if index >= 1 && index <= len {
+                            (index - 1) as usize
+                        } else if index <= -1 && index >= -len {
+                            (len + index) as usize
+                        } else {
+                            panic!("OBJ face index {} is out of range for {} vertices", index, len);
+                        }
+                    })
+                    .collect();
+                for i in 1..polygon.len().saturating_sub(1) {
+                    faces.push([polygon[0], polygon[i], polygon[i + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    (vertices, faces)
+}
+
+fn parse_stl(bytes: &[u8]) -> (Vec<[f64; 3]>, Vec<[usize; 3]>) {
+    if bytes.len() < 84 {
+        panic!("STL file is too short");
+    }
+    let count = u32::from_le_bytes([bytes[80], bytes[81], bytes[82], bytes[83]]) as usize;
+    if bytes.len() < 84 + count * 50 {
+        panic!("STL file is not a binary STL or is truncated");
+    }
+
+    let read_f32 = |offset: usize| {
+        f32::from_le_bytes([bytes[offset], bytes[offset + 1], bytes[offset + 2], bytes[offset + 3]]) as f64
+    };
+
+    // STL stores every triangle on its own, vertices have to be welded for the pseudo-normals to work
+    let mut vertices: Vec<[f64; 3]> = Vec::new();
+    let mut welded: HashMap<[u64; 3], usize> = HashMap::new();
+    let mut faces: Vec<[usize; 3]> = Vec::with_capacity(count);
+    for t in 0..count {
+        let offset = 84 + t * 50 + 12;
+        let mut face = [0; 3];
+        for (k, index) in face.iter_mut().enumerate() {
+            let o = offset + k * 12;
+            let v = [read_f32(o), read_f32(o + 4), read_f32(o + 8)];
+            let key = [v[0].to_bits(), v[1].to_bits(), v[2].to_bits()];
+            *index = *welded.entry(key).or_insert_with(|| {
+                vertices.push(v);
+                vertices.len() - 1
+            });
+        }
+        faces.push(face);
+    }
+    (vertices, faces)
+}
+
+impl TriangleMesh {
+    pub fn new(vertices: Vec<[f64; 3]>, faces: Vec<[usize; 3]>) -> TriangleMesh {
+        let mut vertex_normals = vec![[0.0; 3]; vertices.len()];
+        let mut edge_normals: HashMap<(usize, usize), [f64; 3]> = HashMap::new();
+        let mut triangles: Vec<Triangle> = Vec::with_capacity(faces.len());
+
+        for f in &faces {
+            let a = vertices[f[0]];
+            let b = vertices[f[1]];
+            let c = vertices[f[2]];
+            let cross = vec3_cross(vec3_sub(b, a), vec3_sub(c, a));
+            if vecmath::vec3_len(cross) == 0.0 {
+                continue;
+            }
+            let n = vec3_normalized(cross);
+
+            for k in 0..3 {
+                let p = vertices[f[k]];
+                let e0 = vec3_sub(vertices[f[(k + 1) % 3]], p);
+                let e1 = vec3_sub(vertices[f[(k + 2) % 3]], p);
+                let cos = vec3_dot(vec3_normalized(e0), vec3_normalized(e1)).clamp(-1.0, 1.0);
+                vertex_normals[f[k]] = vec3_add(vertex_normals[f[k]], vec3_scale(n, cos.acos()));
+
+                let key = edge_key(f[k], f[(k + 1) % 3]);
+                let e = edge_normals.entry(key).or_insert([0.0; 3]);
+                *e = vec3_add(*e, n);
+            }
+            triangles.push(Triangle {
+                indices: *f,
+                face_normal: n,
+                edge_normals: [[0.0; 3]; 3],
+            });
+        }
+
+        if triangles.is_empty() {
+            panic!("Mesh does not contain any triangles");
+        }
+
+        for t in triangles.iter_mut() {
+            for k in 0..3 {
+                t.edge_normals[k] = edge_normals[&edge_key(t.indices[k], t.indices[(k + 1) % 3])];
+            }
+        }
+
+        let mut mesh = TriangleMesh {
+            vertices,
+            vertex_normals,
+            order: (0..triangles.len()).collect(),
+            triangles,
+            nodes: Vec::new(),
+        };
+        let count = mesh.triangles.len();
+        mesh.build_node(0, count);
+        mesh
+    }
+
+    pub fn bounds(&self) -> ([f64; 3], [f64; 3]) {
+        (self.nodes[0].min, self.nodes[0].max)
+    }
+
+    fn triangle_bounds(&self, t: usize) -> ([f64; 3], [f64; 3]) {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for &i in &self.triangles[t].indices {
+            let v = self.vertices[i];
+            for k in 0..3 {
+                min[k] = min[k].min(v[k]);
+                max[k] = max[k].max(v[k]);
+            }
+        }
+        (min, max)
+    }
+
+    fn centroid(&self, t: usize) -> [f64; 3] {
+        let i = self.triangles[t].indices;
+        let s = vec3_add(vec3_add(self.vertices[i[0]], self.vertices[i[1]]), self.vertices[i[2]]);
+        vec3_scale(s, 1.0 / 3.0)
+    }
+
+    fn build_node(&mut self, start: usize, count: usize) -> usize {
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        let mut c_min = [f64::MAX; 3];
+        let mut c_max = [f64::MIN; 3];
+        for &t in &self.order[start..start + count] {
+            let (t_min, t_max) = self.triangle_bounds(t);
+            let c = self.centroid(t);
+            for k in 0..3 {
+                min[k] = min[k].min(t_min[k]);
+                max[k] = max[k].max(t_max[k]);
+                c_min[k] = c_min[k].min(c[k]);
+                c_max[k] = c_max[k].max(c[k]);
+            }
+        }
+
+        let node = self.nodes.len();
+        self.nodes.push(BvhNode { min, max, start, count });
+        if count <= LEAF_SIZE {
+            return node;
+        }
+
+        // Median split along the longest axis of the centroids
+        let extent = vec3_sub(c_max, c_min);
+        let axis = if extent[0] > extent[1] && extent[0] > extent[2] {
+            0
+        } else if extent[1] > extent[2] {
+            1
+        } else {
+            2
+        };
+        let mut order: Vec<usize> = self.order[start..start + count].to_vec();
+        order.sort_by(|&a, &b| {
+            self.centroid(a)[axis]
+                .partial_cmp(&self.centroid(b)[axis])
+                .unwrap_or(std::cmp::Ordering::Equal)
+        });
+        self.order[start..start + count].copy_from_slice(&order);
+
+        let half = count / 2;
+        self.build_node(start, half);
+        let right = self.build_node(start + half, count - half);
+        self.nodes[node].start = right;
+        self.nodes[node].count = 0;
+        node
+    }
+
+    pub fn signed_distance(&self, pos: [f64; 3]) -> f64 {
+        let mut best = (f64::MAX, 0.0);
+        self.query(0, pos, &mut best);
+        best.0.sqrt() * best.1
+    }
+
+    // best holds the squared distance and the sign of the closest triangle found so far
+    fn query(&self, node: usize, pos: [f64; 3], best: &mut (f64, f64)) {
+        let n = &self.nodes[node];
+        if n.count > 0 {
+            for &t in &self.order[n.start..n.start + n.count] {
+                let (closest, feature) = self.closest_point(t, pos);
+                let d = vec3_sub(pos, closest);
+                let dist2 = vec3_dot(d, d);
+                if dist2 < best.0 {
+                    let normal = self.pseudo_normal(t, feature);
+                    best.0 = dist2;
+                    best.1 = if vec3_dot(d, normal) < 0.0 { -1.0 } else { 1.0 };
+                }
+            }
+            return;
+        }
+
+        let left = node + 1;
+        let right = n.start;
+        let d_left = self.box_distance2(left, pos);
+        let d_right = self.box_distance2(right, pos);
+        let (first, d_first, second, d_second) = if d_left < d_right {
+            (left, d_left, right, d_right)
+        } else {
+            (right, d_right, left, d_left)
+        };
+        if d_first < best.0 {
+            self.query(first, pos, best);
+        }
+        if d_second < best.0 {
+            self.query(second, pos, best);
+        }
+    }
+
+    fn box_distance2(&self, node: usize, pos: [f64; 3]) -> f64 {
+        let n = &self.nodes[node];
+        let mut d2 = 0.0;
+        for ((min, max), p) in n.min.iter().zip(n.max).zip(pos) {
+            let d = (min - p).max(0.0).max(p - max);
+            d2 += d * d;
+        }
+        d2
+    }
+
+    fn pseudo_normal(&self, t: usize, feature: Feature) -> [f64; 3] {
+        let tri = &self.triangles[t];
+        match feature {
+            Feature::Face => tri.face_normal,
+            Feature::Edge(e) => tri.edge_normals[e],
+            Feature::Vertex(v) => self.vertex_normals[tri.indices[v]],
+        }
+    }
+
+    // Closest point on a triangle, from Ericson's "Real-Time Collision Detection"
+    fn closest_point(&self, t: usize, p: [f64; 3]) -> ([f64; 3], Feature) {
+        let i = self.triangles[t].indices;
+        let a = self.vertices[i[0]];
+        let b = self.vertices[i[1]];
+        let c = self.vertices[i[2]];
+
+        let ab = vec3_sub(b, a);
+        let ac = vec3_sub(c, a);
+        let ap = vec3_sub(p, a);
+        let d1 = vec3_dot(ab, ap);
+        let d2 = vec3_dot(ac, ap);
+        if d1 <= 0.0 && d2 <= 0.0 {
+            return (a, Feature::Vertex(0));
+        }
+
+        let bp = vec3_sub(p, b);
+        let d3 = vec3_dot(ab, bp);
+        let d4 = vec3_dot(ac, bp);
+        if d3 >= 0.0 && d4 <= d3 {
+            return (b, Feature::Vertex(1));
+        }
+
+        let vc = d1 * d4 - d3 * d2;
+        if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+            let v = d1 / (d1 - d3);
+            return (vec3_add(a, vec3_scale(ab, v)), Feature::Edge(0));
+        }
+
+        let cp = vec3_sub(p, c);
+        let d5 = vec3_dot(ab, cp);
+        let d6 = vec3_dot(ac, cp);
+        if d6 >= 0.0 && d5 <= d6 {
+            return (c, Feature::Vertex(2));
+        }
+
+        let vb = d5 * d2 - d1 * d6;
+        if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+            let w = d2 / (d2 - d6);
+            return (vec3_add(a, vec3_scale(ac, w)), Feature::Edge(2));
+        }
+
+        let va = d3 * d6 - d5 * d4;
+        if va <= 0.0 && (d4 - d3) >= 0.0 && (d5 - d6) >= 0.0 {
+            let w = (d4 - d3) / ((d4 - d3) + (d5 - d6));
+            return (vec3_add(b, vec3_scale(vec3_sub(c, b), w)), Feature::Edge(1));
+        }
+
+        let denom = 1.0 / (va + vb + vc);
+        let v = vb * denom;
+        let w = vc * denom;
+        (vec3_add(a, vec3_add(vec3_scale(ab, v), vec3_scale(ac, w))), Feature::Face)
+    }
+}
+
+fn edge_key(a: usize, b: usize) -> (usize, usize) {
+    if a < b {
+        (a, b)
+    } else {
+        (b, a)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stl(triangles: &[[[f32; 3]; 3]]) -> Vec<u8> {
+        let mut bytes = vec![0u8; 80];
+        bytes.extend_from_slice(&(triangles.len() as u32).to_le_bytes());
+        for t in triangles {
+            // The normal is ignored
+            bytes.extend_from_slice(&[0u8; 12]);
+            for v in t {
+                for c in v {
+                    bytes.extend_from_slice(&c.to_le_bytes());
+                }
+            }
+            bytes.extend_from_slice(&[0u8; 2]);
+        }
+        bytes
+    }
+
+    #[test]
+    fn obj_triangulates_polygons_and_resolves_indices() {
+        let obj = "# quad\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nvn 0 0 1\nf 1/1/1 2/2/1 3/3/1 4/4/1\nf -1 -2 -3\n";
+        let (vertices, faces) = parse_obj(obj);
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(vertices[2], [1.0, 1.0, 0.0]);
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 3], [3, 2, 1]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn obj_rejects_invalid_indices() {
+        parse_obj("v 0 0 0\nv 1 0 0\nf 1 2 3\n");
+    }
+
+    #[test]
+    fn stl_welds_shared_vertices() {
+        let a = [0.0, 0.0, 0.0];
+        let b = [1.0, 0.0, 0.0];
+        let c = [0.0, 1.0, 0.0];
+        let d = [0.0, 0.0, 1.0];
+        let (vertices, faces) = parse_stl(&stl(&[[a, b, c], [a, c, d]]));
+        assert_eq!(vertices.len(), 4);
+        assert_eq!(faces, vec![[0, 1, 2], [0, 2, 3]]);
+    }
+
+    #[test]
+    #[should_panic(expected = "truncated")]
+    fn stl_rejects_truncated_files() {
+        let mut bytes = stl(&[[[0.0; 3]; 3]]);
+        bytes.truncate(bytes.len() - 1);
+        parse_stl(&bytes);
+    }
+}
@@ -1,15 +1,21 @@
 use crate::helpers;
 use crate::configuration;
-use std::{rc::Rc, cell::RefCell};
+use crate::noise;
+use crate::script;
+use std::{rc::Rc, cell::{Cell, RefCell}};
 
+// lipschitz() returns an upper bound of how much the modifier can stretch distances. The primitive divides
+// its distance by the product of all bounds, so marching does not step through the surface.
 pub trait PosModifier{
     fn modify(&self, pos: [f64; 3]) -> [f64; 3];
     fn evaluate(&self, t: f64);
+    fn lipschitz(&self) -> f64;
 }
 
 pub trait DistModifier{
-    fn modify(&self, dist: f64) -> f64;
+    fn modify(&self, dist: f64, pos: [f64; 3]) -> f64;
     fn evaluate(&self, t: f64);
+    fn lipschitz(&self) -> f64;
 }
 
 
@@ -28,6 +34,10 @@ impl PosModifier for Distort{
     fn evaluate(&self, t: f64){
         
     }
+    fn lipschitz(&self) -> f64{
+        // Every axis is shifted by the same scalar, whose gradient is at most factor * freq * sqrt(3)
+        return 1.0 + 3.0 * (get_f64!(self.factor) * get_f64!(self.freq)).abs();
+    }
 }
 
 impl Distort{
@@ -47,6 +57,8 @@ impl Distort{
 
 pub struct Twist {
     power: f64!(),
+    // Bounding radius of the object around the twist axis, 0 uses the unit sphere
+    radius: f64!(),
 }
 
 impl PosModifier for Twist{
@@ -59,16 +71,32 @@ impl PosModifier for Twist{
     fn evaluate(&self, t: f64){
 
     }
+    fn lipschitz(&self) -> f64{
+        // Bounds the stretch for points up to the bounding radius from the twist axis
+        return shear_bound(get_f64!(self.power) * bound_radius(&self.radius));
+    }
 }
 
 impl Twist{
-    pub fn new(power: f64!()) -> Twist{
+    pub fn new(power: f64!(), radius: f64!()) -> Twist{
         Twist{
-            power
+            power,
+            radius
         }
     }
 }
 
+fn bound_radius(radius: &f64!()) -> f64{
+    let r = get_f64!(radius);
+    if r > 0.0 { r } else { 1.0 }
+}
+
+// The Jacobian of twist and bend is a shear with the off-diagonal a, its largest singular value is the bound
+fn shear_bound(a: f64) -> f64{
+    let a = a.abs();
+    (a + (a * a + 4.0).sqrt()) * 0.5
+}
+
 
 
 
@@ -81,6 +109,8 @@ impl Twist{
 
 pub struct Bend {
     power: f64!(),
+    // Bounding radius of the object around the origin, 0 uses the unit sphere
+    radius: f64!(),
 }
 
 impl PosModifier for Bend{
@@ -94,12 +124,16 @@ impl PosModifier for Bend{
     fn evaluate(&self, t: f64){
 
     }
+    fn lipschitz(&self) -> f64{
+        return shear_bound(get_f64!(self.power) * bound_radius(&self.radius));
+    }
 }
 
 impl Bend{
-    pub fn new(power: f64!()) -> Bend{
+    pub fn new(power: f64!(), radius: f64!()) -> Bend{
         Bend{
-            power
+            power,
+            radius
         }
     }
 }
@@ -131,6 +165,10 @@ impl PosModifier for Repetition{
     fn evaluate(&self, t: f64){
 
     }
+    fn lipschitz(&self) -> f64{
+        // The cell borders are discontinuous, neighbouring copies are only seen when stepping at half size
+        2.0
+    }
 }
 
 impl Repetition{
@@ -170,6 +208,9 @@ impl PosModifier for RepetitionLimited{
     fn evaluate(&self, t: f64){
 
     }
+    fn lipschitz(&self) -> f64{
+        2.0
+    }
 }
 
 impl RepetitionLimited{
@@ -193,25 +234,431 @@ impl RepetitionLimited{
 
 
 
+pub struct Mirror {
+    axes: [bool;3],
+    offset: [f64!();3]
+}
+
+impl PosModifier for Mirror{
+    fn modify(&self, pos: [f64; 3]) -> [f64; 3]{
+        let offset = get_f64v!(self.offset);
+        let mut p = pos;
+        for i in 0..3 {
+            if self.axes[i] {
+                p[i] = p[i].abs() - offset[i];
+            }
+        }
+        p
+    }
+    fn evaluate(&self, t: f64){
+
+    }
+    fn lipschitz(&self) -> f64{
+        1.0
+    }
+}
+
+impl Mirror{
+    pub fn new(axes: [bool;3], offset: [f64!();3]) -> Mirror{
+        Mirror{
+            axes,
+            offset,
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+pub struct PolarRepetition {
+    axis: usize,
+    count: f64!(),
+    offset: f64!()
+}
+
+impl PosModifier for PolarRepetition{
+    fn modify(&self, pos: [f64; 3]) -> [f64; 3]{
+        let count = get_f64!(self.count).max(1.0);
+        let sector = 2.0 * std::f64::consts::PI / count;
+
+        // u and v span the plane the copies are distributed in
+        let u = (self.axis + 1) % 3;
+        let v = (self.axis + 2) % 3;
+
+        let angle = pos[v].atan2(pos[u]);
+        let r = (pos[u] * pos[u] + pos[v] * pos[v]).sqrt();
+        let a = (angle + 0.5 * sector).rem_euclid(sector) - 0.5 * sector;
+
+        let mut p = pos;
+        p[u] = a.cos() * r - get_f64!(self.offset);
+        p[v] = a.sin() * r;
+        p
+    }
+    fn evaluate(&self, t: f64){
+
+    }
+    fn lipschitz(&self) -> f64{
+        1.0
+    }
+}
+
+impl PolarRepetition{
+    pub fn new(axis: usize, count: f64!(), offset: f64!()) -> PolarRepetition{
+        PolarRepetition{
+            axis,
+            count,
+            offset,
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+pub struct Kaleidoscope {
+    iterations: f64!(),
+    angle: f64!(),
+    offset: [f64!();3]
+}
+
+impl PosModifier for Kaleidoscope{
+    fn modify(&self, pos: [f64; 3]) -> [f64; 3]{
+        let iterations = get_f64!(self.iterations).round().max(0.0) as u32;
+        let angle = get_f64!(self.angle).to_radians();
+        let (s, c) = angle.sin_cos();
+        let offset = get_f64v!(self.offset);
+
+        let mut p = pos;
+        for _ in 0..iterations {
+            p = [p[0].abs(), p[1].abs(), p[2].abs()];
+
+            // Reflect across the x=y, x=z and y=z planes
+            if p[0] < p[1] {
+                p.swap(0, 1);
+            }
+            if p[0] < p[2] {
+                p.swap(0, 2);
+            }
+            if p[1] < p[2] {
+                p.swap(1, 2);
+            }
+
+            p = [c * p[0] - s * p[1], s * p[0] + c * p[1], p[2]];
+            p = [p[0] - offset[0], p[1] - offset[1], p[2] - offset[2]];
+        }
+        p
+    }
+    fn evaluate(&self, t: f64){
+
+    }
+    fn lipschitz(&self) -> f64{
+        1.0
+    }
+}
+
+impl Kaleidoscope{
+    pub fn new(iterations: f64!(), angle: f64!(), offset: [f64!();3]) -> Kaleidoscope{
+        Kaleidoscope{
+            iterations,
+            angle,
+            offset,
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
 pub struct Round {
-    thickness: f64,
+    radius: f64!(),
 }
 
 
 impl DistModifier for Round{
-    fn modify(&self, dist: f64) -> f64{
-        return dist.abs() - self.thickness;
+    fn modify(&self, dist: f64, pos: [f64; 3]) -> f64{
+        return dist - get_f64!(self.radius);
     }
     fn evaluate(&self, t: f64){
 
     }
+    fn lipschitz(&self) -> f64{
+        1.0
+    }
 }
 
 impl Round{
-    pub fn new(thickness: f64) -> Round{
+    pub fn new(radius: f64!()) -> Round{
         Round{
+            radius,
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+pub struct Onion {
+    thickness: f64!(),
+}
+
+
+impl DistModifier for Onion{
+    fn modify(&self, dist: f64, pos: [f64; 3]) -> f64{
+        return dist.abs() - get_f64!(self.thickness);
+    }
+    fn evaluate(&self, t: f64){
+
+    }
+    fn lipschitz(&self) -> f64{
+        1.0
+    }
+}
+
+impl Onion{
+    pub fn new(thickness: f64!()) -> Onion{
+        Onion{
             thickness,
         }
     }
 }
 
+
+
+
+
+
+
+
+
+
+
+
+
+pub struct Displacement {
+    factor: f64!(),
+    offset: [f64!();3],
+    freq: f64!()
+}
+
+impl DistModifier for Displacement{
+    fn modify(&self, dist: f64, pos: [f64; 3]) -> f64{
+        let freq = get_f64!(self.freq);
+        let d = (freq * pos[0] + get_f64!(self.offset[0])).sin() * (freq * pos[1] + get_f64!(self.offset[1])).sin() * (freq * pos[2] + get_f64!(self.offset[2])).sin() * get_f64!(self.factor);
+        dist + d
+    }
+    fn evaluate(&self, t: f64){
+
+    }
+    fn lipschitz(&self) -> f64{
+        return 1.0 + 3.0_f64.sqrt() * (get_f64!(self.factor) * get_f64!(self.freq)).abs();
+    }
+}
+
+impl Displacement{
+    pub fn new(factor: f64!(), offset: [f64!();3], freq: f64!()) -> Displacement{
+        Displacement{
+            factor,offset,freq
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+// Shared sampling for the noise modifiers. speed * t shifts the sampled field over time, which makes
+// surfaces boil. With octaves > 1 the noise is layered as fBm with a gain of 0.5.
+fn sample_noise(noise: &noise::Noise, pos: [f64; 3], freq: f64, octaves: f64, lacunarity: f64, time_offset: f64) -> f64{
+    let p = [pos[0] * freq + time_offset, pos[1] * freq + time_offset, pos[2] * freq + time_offset];
+    let octaves = octaves.round().max(1.0) as u32;
+    if octaves == 1 {
+        return noise.sample(p);
+    }
+    noise.fbm(p, octaves, lacunarity, 0.5)
+}
+
+// Gradient bound of sample_noise
+fn noise_lipschitz(noise: &noise::Noise, freq: f64, octaves: f64, lacunarity: f64) -> f64{
+    let octaves = octaves.round().max(1.0) as u32;
+    noise.fbm_gradient_bound(octaves, lacunarity, 0.5) * freq.abs()
+}
+
+pub struct NoiseDistort {
+    noise: noise::Noise,
+    freq: f64!(),
+    amplitude: f64!(),
+    speed: f64!(),
+    octaves: f64!(),
+    lacunarity: f64!(),
+    time: Cell<f64>,
+}
+
+impl PosModifier for NoiseDistort{
+    fn modify(&self, pos: [f64; 3]) -> [f64; 3]{
+        let freq = get_f64!(self.freq);
+        let amplitude = get_f64!(self.amplitude);
+        let octaves = get_f64!(self.octaves);
+        let lacunarity = get_f64!(self.lacunarity);
+        let time_offset = get_f64!(self.speed) * self.time.get();
+
+        // Decorrelated samples per axis, so the warp is a vector field and not a shift along the diagonal
+        let x = sample_noise(&self.noise, pos, freq, octaves, lacunarity, time_offset);
+        let y = sample_noise(&self.noise, [pos[0] + 31.416, pos[1] - 47.853, pos[2] + 12.793], freq, octaves, lacunarity, time_offset);
+        let z = sample_noise(&self.noise, [pos[0] - 19.173, pos[1] + 23.917, pos[2] - 61.237], freq, octaves, lacunarity, time_offset);
+        [pos[0] + x * amplitude, pos[1] + y * amplitude, pos[2] + z * amplitude]
+    }
+    fn evaluate(&self, t: f64){
+        self.time.set(t);
+    }
+    fn lipschitz(&self) -> f64{
+        let bound = noise_lipschitz(&self.noise, get_f64!(self.freq), get_f64!(self.octaves), get_f64!(self.lacunarity));
+        return 1.0 + 3.0_f64.sqrt() * bound * get_f64!(self.amplitude).abs();
+    }
+}
+
+impl NoiseDistort{
+    pub fn new(noise: noise::Noise, freq: f64!(), amplitude: f64!(), speed: f64!(), octaves: f64!(), lacunarity: f64!()) -> NoiseDistort{
+        NoiseDistort{
+            noise,freq,amplitude,speed,octaves,lacunarity,time: Cell::new(0.0)
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+pub struct NoiseDisplacement {
+    noise: noise::Noise,
+    freq: f64!(),
+    amplitude: f64!(),
+    speed: f64!(),
+    octaves: f64!(),
+    lacunarity: f64!(),
+    time: Cell<f64>,
+}
+
+impl DistModifier for NoiseDisplacement{
+    fn modify(&self, dist: f64, pos: [f64; 3]) -> f64{
+        let time_offset = get_f64!(self.speed) * self.time.get();
+        let n = sample_noise(&self.noise, pos, get_f64!(self.freq), get_f64!(self.octaves), get_f64!(self.lacunarity), time_offset);
+        return dist + n * get_f64!(self.amplitude);
+    }
+    fn evaluate(&self, t: f64){
+        self.time.set(t);
+    }
+    fn lipschitz(&self) -> f64{
+        let bound = noise_lipschitz(&self.noise, get_f64!(self.freq), get_f64!(self.octaves), get_f64!(self.lacunarity));
+        return 1.0 + bound * get_f64!(self.amplitude).abs();
+    }
+}
+
+impl NoiseDisplacement{
+    pub fn new(noise: noise::Noise, freq: f64!(), amplitude: f64!(), speed: f64!(), octaves: f64!(), lacunarity: f64!()) -> NoiseDisplacement{
+        NoiseDisplacement{
+            noise,freq,amplitude,speed,octaves,lacunarity,time: Cell::new(0.0)
+        }
+    }
+}
+
+
+
+
+
+
+
+
+
+
+
+
+
+// The script defines fn modify(p, t) returning the new position. An optional fn lipschitz() returns the
+// bound of the warp, without it the distances are not scaled.
+pub struct ScriptModifier {
+    script: script::Script,
+    time: Cell<f64>,
+}
+
+impl PosModifier for ScriptModifier{
+    fn modify(&self, pos: [f64; 3]) -> [f64; 3]{
+        let res = script::dynamic_to_vec(self.script.call("modify", vec![script::vec3_to_dynamic(pos), rhai::Dynamic::from_float(self.time.get())]));
+        if res.len() < 3 {
+            panic!("Script fn modify(p, t) has to return an array of 3 numbers");
+        }
+        [res[0], res[1], res[2]]
+    }
+    fn evaluate(&self, t: f64){
+        self.time.set(t);
+    }
+    fn lipschitz(&self) -> f64{
+        if self.script.has_fn("lipschitz", 0) {
+            return script::dynamic_to_f64(&self.script.call("lipschitz", vec![])).max(1.0);
+        }
+        1.0
+    }
+}
+
+impl ScriptModifier{
+    pub fn new(path: &str, params: Vec<f64!()>) -> ScriptModifier{
+        let script = script::Script::load(path, params);
+        if !script.has_fn("modify", 2) {
+            panic!("Script {} has no fn modify(p, t)", path);
+        }
+        ScriptModifier{
+            script,time: Cell::new(0.0)
+        }
+    }
+}
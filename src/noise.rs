@@ -0,0 +1,246 @@
+// Perlin and simplex noise follow Ken Perlin's improved noise and Stefan Gustavson's simplex noise paper.
+// All noise is generated from a seeded permutation table, so the same seed always produces the same field.
+
+#[derive(Clone, Copy)]
+pub enum NoiseType {
+    Perlin,
+    Simplex,
+    Voronoi,
+}
+
+pub struct Noise {
+    perm: [u8; 512],
+    noise_type: NoiseType,
+}
+
+const GRAD3: [[f64; 3]; 12] = [
+    [1.0, 1.0, 0.0], [-1.0, 1.0, 0.0], [1.0, -1.0, 0.0], [-1.0, -1.0, 0.0],
+    [1.0, 0.0, 1.0], [-1.0, 0.0, 1.0], [1.0, 0.0, -1.0], [-1.0, 0.0, -1.0],
+    [0.0, 1.0, 1.0], [0.0, -1.0, 1.0], [0.0, 1.0, -1.0], [0.0, -1.0, -1.0],
+];
+
+impl Noise {
+    pub fn new(seed: u32, noise_type: NoiseType) -> Noise {
+        let mut p: [u8; 256] = std::array::from_fn(|i| i as u8);
+
+        // xorshift, so the table does not depend on the rand crate's generator
+        let mut state = seed as u64 ^ 0x9E37_79B9_7F4A_7C15;
+        for i in (1..256).rev() {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            let j = (state % (i as u64 + 1)) as usize;
+            p.swap(i, j);
+        }
+
+        let mut perm = [0u8; 512];
+        for i in 0..512 {
+            perm[i] = p[i & 255];
+        }
+        Noise { perm, noise_type }
+    }
+
+    // Perlin and simplex return roughly [-1, 1], voronoi returns the distance to the closest feature point [0, ~0.9]
+    pub fn sample(&self, pos: [f64; 3]) -> f64 {
+        match self.noise_type {
+            NoiseType::Perlin => self.perlin(pos),
+            NoiseType::Simplex => self.simplex(pos),
+            NoiseType::Voronoi => self.voronoi(pos),
+        }
+    }
+
+    // Approximate upper bound of the gradient length of sample(), voronoi distances are exactly 1-Lipschitz
+    pub fn gradient_bound(&self) -> f64 {
+        match self.noise_type {
+            NoiseType::Perlin => 2.5,
+            NoiseType::Simplex => 2.5,
+            NoiseType::Voronoi => 1.0,
+        }
+    }
+
+    // Every fBm octave adds lacunarity times the slope at gain times the weight
+    pub fn fbm_gradient_bound(&self, octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut bound = 0.0;
+        let mut norm = 0.0;
+        for i in 0..octaves.max(1) as i32 {
+            bound += (gain * lacunarity).powi(i);
+            norm += gain.powi(i);
+        }
+        self.gradient_bound() * bound / norm
+    }
+
+    pub fn fbm(&self, pos: [f64; 3], octaves: u32, lacunarity: f64, gain: f64) -> f64 {
+        let mut p = pos;
+        let mut amplitude = 1.0;
+        let mut total = 0.0;
+        let mut norm = 0.0;
+        for _ in 0..octaves.max(1) {
+            total += self.sample(p) * amplitude;
+            norm += amplitude;
+            amplitude *= gain;
+            p = [p[0] * lacunarity, p[1] * lacunarity, p[2] * lacunarity];
+        }
+        total / norm
+    }
+
+    #[inline(always)]
+    fn hash(&self, x: i32, y: i32, z: i32) -> usize {
+        let x = (x & 255) as usize;
+        let y = (y & 255) as usize;
+        let z = (z & 255) as usize;
+        self.perm[self.perm[self.perm[x] as usize + y] as usize + z] as usize
+    }
+
+    fn perlin(&self, pos: [f64; 3]) -> f64 {
+        let xf = pos[0].floor();
+        let yf = pos[1].floor();
+        let zf = pos[2].floor();
+        let (xi, yi, zi) = (xf as i32, yf as i32, zf as i32);
+        let (x, y, z) = (pos[0] - xf, pos[1] - yf, pos[2] - zf);
+
+        let u = fade(x);
+        let v = fade(y);
+        let w = fade(z);
+
+        let g = |dx: i32, dy: i32, dz: i32| {
+            let grad = GRAD3[self.hash(xi + dx, yi + dy, zi + dz) % 12];
+            grad[0] * (x - dx as f64) + grad[1] * (y - dy as f64) + grad[2] * (z - dz as f64)
+        };
+
+        lerp(
+            lerp(
+                lerp(g(0, 0, 0), g(1, 0, 0), u),
+                lerp(g(0, 1, 0), g(1, 1, 0), u),
+                v,
+            ),
+            lerp(
+                lerp(g(0, 0, 1), g(1, 0, 1), u),
+                lerp(g(0, 1, 1), g(1, 1, 1), u),
+                v,
+            ),
+            w,
+        )
+    }
+
+    fn simplex(&self, pos: [f64; 3]) -> f64 {
+        const F3: f64 = 1.0 / 3.0;
+        const G3: f64 = 1.0 / 6.0;
+
+        let s = (pos[0] + pos[1] + pos[2]) * F3;
+        let i = (pos[0] + s).floor();
+        let j = (pos[1] + s).floor();
+        let k = (pos[2] + s).floor();
+        let t = (i + j + k) * G3;
+        let x0 = pos[0] - (i - t);
+        let y0 = pos[1] - (j - t);
+        let z0 = pos[2] - (k - t);
+
+        let (i1, j1, k1, i2, j2, k2) = if x0 >= y0 {
+            if y0 >= z0 {
+                (1, 0, 0, 1, 1, 0)
+            } else if x0 >= z0 {
+                (1, 0, 0, 1, 0, 1)
+            } else {
+                (0, 0, 1, 1, 0, 1)
+            }
+        } else if y0 < z0 {
+            (0, 0, 1, 0, 1, 1)
+        } else if x0 < z0 {
+            (0, 1, 0, 0, 1, 1)
+        } else {
+            (0, 1, 0, 1, 1, 0)
+        };
+
+        let corners = [
+            (0, 0, 0, x0, y0, z0),
+            (i1, j1, k1, x0 - i1 as f64 + G3, y0 - j1 as f64 + G3, z0 - k1 as f64 + G3),
+            (i2, j2, k2, x0 - i2 as f64 + 2.0 * G3, y0 - j2 as f64 + 2.0 * G3, z0 - k2 as f64 + 2.0 * G3),
+            (1, 1, 1, x0 - 1.0 + 3.0 * G3, y0 - 1.0 + 3.0 * G3, z0 - 1.0 + 3.0 * G3),
+        ];
+
+        let (ii, jj, kk) = (i as i32, j as i32, k as i32);
+        let mut n = 0.0;
+        for (di, dj, dk, x, y, z) in corners {
+            let mut t = 0.6 - x * x - y * y - z * z;
+            if t > 0.0 {
+                let grad = GRAD3[self.hash(ii + di, jj + dj, kk + dk) % 12];
+                t *= t;
+                n += t * t * (grad[0] * x + grad[1] * y + grad[2] * z);
+            }
+        }
+        32.0 * n
+    }
+
+    fn voronoi(&self, pos: [f64; 3]) -> f64 {
+        let xf = pos[0].floor();
+        let yf = pos[1].floor();
+        let zf = pos[2].floor();
+        let (xi, yi, zi) = (xf as i32, yf as i32, zf as i32);
+
+        let mut min_dist: f64 = 8.0;
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                for dz in -1..=1 {
+                    let h = self.hash(xi + dx, yi + dy, zi + dz);
+                    let feature = [
+                        xf + dx as f64 + self.perm[h] as f64 / 255.0,
+                        yf + dy as f64 + self.perm[h + 1] as f64 / 255.0,
+                        zf + dz as f64 + self.perm[h + 2] as f64 / 255.0,
+                    ];
+                    let d = [feature[0] - pos[0], feature[1] - pos[1], feature[2] - pos[2]];
+                    min_dist = min_dist.min(d[0] * d[0] + d[1] * d[1] + d[2] * d[2]);
+                }
+            }
+        }
+        min_dist.sqrt()
+    }
+}
+
+#[inline(always)]
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+#[inline(always)]
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + t * (b - a)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const POINTS: [[f64; 3]; 4] = [[0.1, 0.2, 0.3], [1.5, -2.25, 3.75], [-7.3, 0.4, 12.9], [100.2, 50.6, -3.3]];
+
+    #[test]
+    fn same_seed_gives_same_field() {
+        for noise_type in [NoiseType::Perlin, NoiseType::Simplex, NoiseType::Voronoi] {
+            let a = Noise::new(42, noise_type);
+            let b = Noise::new(42, noise_type);
+            assert_eq!(a.perm, b.perm);
+            for p in POINTS {
+                assert_eq!(a.sample(p), b.sample(p));
+                assert_eq!(a.fbm(p, 4, 2.0, 0.5), b.fbm(p, 4, 2.0, 0.5));
+            }
+        }
+    }
+
+    #[test]
+    fn different_seeds_give_different_fields() {
+        let a = Noise::new(1, NoiseType::Perlin);
+        let b = Noise::new(2, NoiseType::Perlin);
+        assert_ne!(a.perm, b.perm);
+        assert!(POINTS.iter().any(|p| a.sample(*p) != b.sample(*p)));
+    }
+
+    #[test]
+    fn permutation_is_a_permutation() {
+        let noise = Noise::new(7, NoiseType::Simplex);
+        let mut seen = [false; 256];
+        for v in &noise.perm[..256] {
+            seen[*v as usize] = true;
+        }
+        assert!(seen.iter().all(|s| *s));
+        assert_eq!(noise.perm[..256], noise.perm[256..]);
+    }
+}
@@ -1,7 +1,15 @@
 use crate::configuration;
+use crate::csg;
+use crate::curve;
+use crate::distance_grid;
+use crate::heightfield;
 use crate::helpers;
 use crate::helpers::min_f64;
+use crate::instancer;
+use crate::mesh;
 use crate::modifier;
+use crate::script;
+use crate::sdf2d;
 use std::{cell::RefCell, rc::Rc};
 use vecmath;
 
@@ -12,12 +20,77 @@ pub enum GroupModifierType {
     SmoothUnion = 4,
     SmoothSubtraction = 5,
     SmoothIntersection = 6,
+    ChamferUnion = 7,
+    ChamferSubtraction = 8,
+    ChamferIntersection = 9,
+    RoundUnion = 10,
+    RoundSubtraction = 11,
+    RoundIntersection = 12,
+    ColumnsUnion = 13,
+    ColumnsSubtraction = 14,
+    ColumnsIntersection = 15,
+    StairsUnion = 16,
+    StairsSubtraction = 17,
+    StairsIntersection = 18,
+    Groove = 19,
+    Tongue = 20,
+    Engrave = 21,
+    ExpSmoothUnion = 22,
+    PowSmoothUnion = 23,
 }
+
+impl GroupModifierType {
+    pub fn from_code(code: i32) -> Option<GroupModifierType> {
+        match code {
+            1 => Some(GroupModifierType::Union),
+            2 => Some(GroupModifierType::Subtraction),
+            3 => Some(GroupModifierType::Intersection),
+            4 => Some(GroupModifierType::SmoothUnion),
+            5 => Some(GroupModifierType::SmoothSubtraction),
+            6 => Some(GroupModifierType::SmoothIntersection),
+            7 => Some(GroupModifierType::ChamferUnion),
+            8 => Some(GroupModifierType::ChamferSubtraction),
+            9 => Some(GroupModifierType::ChamferIntersection),
+            10 => Some(GroupModifierType::RoundUnion),
+            11 => Some(GroupModifierType::RoundSubtraction),
+            12 => Some(GroupModifierType::RoundIntersection),
+            13 => Some(GroupModifierType::ColumnsUnion),
+            14 => Some(GroupModifierType::ColumnsSubtraction),
+            15 => Some(GroupModifierType::ColumnsIntersection),
+            16 => Some(GroupModifierType::StairsUnion),
+            17 => Some(GroupModifierType::StairsSubtraction),
+            18 => Some(GroupModifierType::StairsIntersection),
+            19 => Some(GroupModifierType::Groove),
+            20 => Some(GroupModifierType::Tongue),
+            21 => Some(GroupModifierType::Engrave),
+            22 => Some(GroupModifierType::ExpSmoothUnion),
+            23 => Some(GroupModifierType::PowSmoothUnion),
+            _ => None,
+        }
+    }
+}
+#[derive(Clone)]
 pub struct Material {
     pub albedo: f64!(),
     pub specular: f64!(),
     pub diffuse: f64!(),
     pub n_specular: f64!(),
+    // Used by the metallic/roughness BRDF, the base color is scaled by albedo
+    pub color: [f64!(); 3],
+    pub metallic: f64!(),
+    pub roughness: f64!(),
+    // Added to the shaded color, so emissive surfaces are visible without lights
+    pub emission: [f64!(); 3],
+    pub emission_strength: f64!(),
+    // Index of the texture set in the texture registry
+    pub texture: Option<usize>,
+}
+// Object space of the innermost textured primitive at the mapped position
+#[derive(Clone, Copy)]
+pub struct TextureSpace {
+    pub pos: [f64; 3],
+    // World to object space for normals, the inverse transpose of the world to object transformation
+    pub frame: [[f64; 3]; 3],
 }
 #[derive(Clone, Copy)]
 pub struct MaterialRaw {
@@ -25,22 +98,67 @@ pub struct MaterialRaw {
     pub specular: f64,
     pub diffuse: f64,
     pub n_specular: f64,
+    pub color: [f64; 3],
+    pub metallic: f64,
+    pub roughness: f64,
+    pub emission: [f64; 3],
+    pub emission_strength: f64,
+    pub texture: Option<usize>,
+    pub texture_space: Option<TextureSpace>,
 }
 
 impl MaterialRaw {
-    pub fn new(
-        albedo: f64!(),
-        specular: f64!(),
-        diffuse: f64!(),
-        n_specular: f64!(),
-    ) -> MaterialRaw {
+    pub fn new(m: &Material) -> MaterialRaw {
         MaterialRaw {
-            albedo: get_ref_f64!(albedo),
-            diffuse: get_ref_f64!(diffuse),
-            n_specular: get_ref_f64!(n_specular),
-            specular: get_ref_f64!(specular),
+            albedo: get_ref_f64!(m.albedo),
+            diffuse: get_ref_f64!(m.diffuse),
+            n_specular: get_ref_f64!(m.n_specular),
+            specular: get_ref_f64!(m.specular),
+            color: get_ref_f64v!(m.color),
+            metallic: get_ref_f64!(m.metallic),
+            roughness: get_ref_f64!(m.roughness),
+            emission: get_ref_f64v!(m.emission),
+            emission_strength: get_ref_f64!(m.emission_strength),
+            texture: m.texture,
+            texture_space: None,
         }
     }
+
+    // Linear interpolation with the same convention as helpers::mix, f = 0 returns a
+    pub fn mix(a: MaterialRaw, b: MaterialRaw, f: f64) -> MaterialRaw {
+        MaterialRaw {
+            albedo: helpers::mix(a.albedo, b.albedo, f),
+            specular: helpers::mix(a.specular, b.specular, f),
+            diffuse: helpers::mix(a.diffuse, b.diffuse, f),
+            n_specular: helpers::mix(a.n_specular, b.n_specular, f),
+            color: helpers::vec_interpolate(a.color, b.color, f),
+            metallic: helpers::mix(a.metallic, b.metallic, f),
+            roughness: helpers::mix(a.roughness, b.roughness, f),
+            emission: helpers::vec_interpolate(a.emission, b.emission, f),
+            emission_strength: helpers::mix(a.emission_strength, b.emission_strength, f),
+            // Textures can't be blended before they are sampled, the closer material keeps its texture
+            texture: if f < 0.5 { a.texture } else { b.texture },
+            texture_space: if f < 0.5 { a.texture_space } else { b.texture_space },
+        }
+    }
+
+    pub fn emitted(&self) -> [f64; 3] {
+        [
+            self.emission[0] * self.emission_strength,
+            self.emission[1] * self.emission_strength,
+            self.emission[2] * self.emission_strength,
+        ]
+    }
+}
+
+fn mix_fractal_data(a: [f64; 5], b: [f64; 5], f: f64) -> [f64; 5] {
+    [
+        helpers::mix(a[0], b[0], f),
+        helpers::mix(a[1], b[1], f),
+        helpers::mix(a[2], b[2], f),
+        helpers::mix(a[3], b[3], f),
+        helpers::mix(a[4], b[4], f),
+    ]
 }
 
 impl Material {
@@ -50,6 +168,12 @@ impl Material {
             specular,
             diffuse,
             n_specular,
+            color: f64v!([1.0, 1.0, 1.0]),
+            metallic: f64!(0.0),
+            roughness: f64!(0.5),
+            emission: f64v!([0.0, 0.0, 0.0]),
+            emission_strength: f64!(0.0),
+            texture: None,
         }
     }
 
@@ -59,6 +183,12 @@ impl Material {
             specular: f64!(0.2),
             diffuse: f64!(0.8),
             n_specular: f64!(10.0),
+            color: f64v!([1.0, 1.0, 1.0]),
+            metallic: f64!(0.0),
+            roughness: f64!(0.5),
+            emission: f64v!([0.0, 0.0, 0.0]),
+            emission_strength: f64!(0.0),
+            texture: None,
         }
     }
 
@@ -68,13 +198,20 @@ impl Material {
             specular: f64!(0.0),
             diffuse: f64!(1.0),
             n_specular: f64!(0.0),
+            color: f64v!([1.0, 1.0, 1.0]),
+            metallic: f64!(0.0),
+            roughness: f64!(0.5),
+            emission: f64v!([0.0, 0.0, 0.0]),
+            emission_strength: f64!(0.0),
+            texture: None,
         }
     }
 }
 
 pub struct PrimitiveResult {
     pub distance: f64,
-    pub fractal_data: [f64; 4],
+    // Orbit traps of fractals: minimum |x|, |y|, |z| and squared radius of the orbit, then the smooth iteration count
+    pub fractal_data: [f64; 5],
     pub material: MaterialRaw,
 }
 
@@ -112,6 +249,10 @@ pub trait Primitive {
     fn map_primitive_mapped(&self, pos: [f64; 3]) -> PrimitiveResult;
     fn map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult;
     fn evaluate(&mut self, t: f64);
+    // Replaces the Lipschitz bound computed from the modifiers
+    fn set_lipschitz_override(&mut self, bound: f64!());
+    // The primitive's transformation becomes relative to the parent
+    fn set_parent(&mut self, parent: Rc<Transform>);
 }
 
 pub trait InternalPrimitive {
@@ -122,12 +263,82 @@ pub trait InternalPrimitive {
     fn _use_raw_position(&self) -> bool;
 }
 
+// An animated transformation that primitives can be parented to. It shares its values with the animation, so
+// children follow the parent's keyframes.
+pub struct Transform {
+    pos: [f64!(); 3],
+    rot: [f64!(); 3],
+    scale: [f64!(); 3],
+    parent: Option<Rc<Transform>>,
+}
+
+impl Transform {
+    pub fn new(pos: [f64!(); 3], rot: [f64!(); 3], scale: [f64!(); 3], parent: Option<Rc<Transform>>) -> Transform {
+        Transform {
+            pos,
+            rot,
+            scale,
+            parent,
+        }
+    }
+
+    // Local to world, including all parents
+    pub fn matrix(&self) -> [[f64; 4]; 4] {
+        let local = helpers::mat_transformation(
+            get_ref_f64v!(self.pos),
+            get_ref_f64v!(self.rot),
+            get_ref_f64v!(self.scale),
+        );
+        match &self.parent {
+            Some(parent) => vecmath::row_mat4_mul(local, parent.matrix()),
+            None => local,
+        }
+    }
+}
+
 pub struct PrimitiveData {
     pos: [f64!(); 3],
     rot: [f64!(); 3],
     scale: [f64!(); 3],
+    parent: Option<Rc<Transform>>,
+    // Inverse of the composed transformation, updated once per frame by evaluate
     mat_inv: [[f64; 4]; 4],
+    // Maps world normals into object space, None while the transformation is singular
+    normal_frame: Option<[[f64; 3]; 3]>,
     pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+    dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+    lipschitz_override: Option<f64!()>,
+    step_scale: f64,
+}
+
+impl PrimitiveData {
+    fn update_mat_inv(&mut self) {
+        let mut mat = helpers::mat_transformation(
+            get_ref_f64v!(self.pos),
+            get_ref_f64v!(self.rot),
+            get_ref_f64v!(self.scale),
+        );
+        if let Some(parent) = &self.parent {
+            mat = vecmath::row_mat4_mul(mat, parent.matrix());
+        }
+        self.mat_inv = vecmath::mat4_inv(mat);
+        self.normal_frame = normal_frame(mat);
+    }
+}
+
+// Normals transform with the inverse transpose of the position transformation. Positions are mapped into object
+// space by the inverse of mat, so with row vectors the transposed rotation and scale part of mat maps normals.
+pub fn normal_frame(mat: [[f64; 4]; 4]) -> Option<[[f64; 3]; 3]> {
+    let frame = [
+        [mat[0][0], mat[1][0], mat[2][0]],
+        [mat[0][1], mat[1][1], mat[2][1]],
+        [mat[0][2], mat[1][2], mat[2][2]],
+    ];
+    let det = vecmath::mat3_det(frame);
+    if !det.is_finite() || det.abs() < 1e-12 {
+        return None;
+    }
+    Some(frame)
 }
 
 fn get_primitive_data(
@@ -135,19 +346,22 @@ fn get_primitive_data(
     rot: [f64!(); 3],
     scale: [f64!(); 3],
     pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+    dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
 ) -> PrimitiveData {
-    let mat_inv = vecmath::mat4_inv(helpers::mat_transformation(
-        get_ref_f64v!(pos),
-        get_ref_f64v!(rot),
-        get_ref_f64v!(scale),
-    ));
-    PrimitiveData {
+    let mut data = PrimitiveData {
         pos,
         rot,
         scale,
-        mat_inv,
+        parent: None,
+        mat_inv: vecmath::mat4_id(),
+        normal_frame: None,
         pos_modifier,
-    }
+        dist_modifier,
+        lipschitz_override: None,
+        step_scale: 1.0,
+    };
+    data.update_mat_inv();
+    data
 }
 
 impl<T> Primitive for T
@@ -155,16 +369,39 @@ where
     T: InternalPrimitive,
 {
     fn map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let data = self.get_primitive_data();
+        let mut transformed_pos = pos;
         if !self._use_raw_position() {
-            let data = self.get_primitive_data();
-            let mut transformed_pos = helpers::mat_vec_mul(data.mat_inv, pos);
+            transformed_pos = helpers::mat_vec_mul(data.mat_inv, pos);
 
             for m in &data.pos_modifier {
                 transformed_pos = m.modify(transformed_pos)
             }
-            return self._map_primitive(transformed_pos);
         }
-        return self._map_primitive(pos);
+        let mut result = self._map_primitive(transformed_pos);
+
+        // Distance modifiers run on the final distance, in object space
+        for m in &data.dist_modifier {
+            result.distance = m.modify(result.distance, transformed_pos);
+        }
+        result.distance *= data.step_scale;
+
+        // The innermost primitive records its object space, outer groups add their transformation to the frame.
+        // Singular transformations leave the frame as it is.
+        if result.material.texture.is_some() {
+            let frame = data.normal_frame.unwrap_or(vecmath::mat3_id());
+            result.material.texture_space = Some(match result.material.texture_space {
+                Some(space) => TextureSpace {
+                    pos: space.pos,
+                    frame: vecmath::row_mat3_mul(frame, space.frame),
+                },
+                None => TextureSpace {
+                    pos: transformed_pos,
+                    frame,
+                },
+            });
+        }
+        result
     }
     
     fn map_primitive_mapped(&self, pos: [f64; 3]) -> PrimitiveResult {
@@ -173,131 +410,189 @@ where
 
     fn evaluate(&mut self, t: f64) {
         let data = self.get_primitive_data_mut();
-        let mat_inv = vecmath::mat4_inv(helpers::mat_transformation(
-            get_ref_f64v!(data.pos),
-            get_ref_f64v!(data.rot),
-            get_ref_f64v!(data.scale),
-        ));
-        data.mat_inv = mat_inv;
+        data.update_mat_inv();
 
         for pos_m in &data.pos_modifier {
             pos_m.evaluate(t);
         }
+        for dist_m in &data.dist_modifier {
+            dist_m.evaluate(t);
+        }
+
+        let lipschitz = match &data.lipschitz_override {
+            Some(bound) => get_ref_f64!(bound),
+            None => {
+                let mut bound = 1.0;
+                for pos_m in &data.pos_modifier {
+                    bound *= pos_m.lipschitz();
+                }
+                for dist_m in &data.dist_modifier {
+                    bound *= dist_m.lipschitz();
+                }
+                bound
+            }
+        };
+        data.step_scale = 1.0 / helpers::max_f64(lipschitz, 1e-6);
 
         self._evaluate(t)
     }
+
+    fn set_lipschitz_override(&mut self, bound: f64!()) {
+        self.get_primitive_data_mut().lipschitz_override = Some(bound);
+    }
+
+    fn set_parent(&mut self, parent: Rc<Transform>) {
+        let data = self.get_primitive_data_mut();
+        data.parent = Some(parent);
+        data.update_mat_inv();
+    }
 }
 
 // ------------------------------------------
 //                  SPHERE
 // ------------------------------------------
 
+enum BlendPick {
+    Closer,
+    Further,
+    First,
+}
+
+// Closer to the bounds the children are evaluated, so rays never stop at the bounding sphere itself
+const GROUP_BOUNDS_MARGIN: f64 = 0.01;
+
 pub struct GroupPrimitive {
     primtive_data: PrimitiveData,
     group_primitive_type: GroupModifierType,
     prims: Vec<Box<dyn Primitive>>,
     factor_f: f64!(),
     factor: f64,
+    // Step or column count, or the second radius of groove and tongue
+    factor2_f: f64!(),
+    factor2: f64,
+    // Sphere in local space that contains all children before blending, outside of it and the blend margin only
+    // its distance is returned
+    bounds: Option<([f64; 3], f64)>,
 }
 
 impl GroupPrimitive {
-    pub fn new_union(prims: Vec<Box<dyn Primitive>>) -> GroupPrimitive {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        group_primitive_type: GroupModifierType,
+        prims: Vec<Box<dyn Primitive>>,
+        factor: f64!(),
+        factor2: f64!(),
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+    ) -> GroupPrimitive {
+        let f = get_f64!(factor);
+        let f2 = get_f64!(factor2);
         GroupPrimitive {
-            group_primitive_type: GroupModifierType::Union,
-            prims: prims,
-            primtive_data: get_primitive_data(
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([1.0, 1.0, 1.0]),
-                Vec::new(),
-            ),
-            factor_f: f64!(0.0),
-            factor: 0.0,
+            group_primitive_type,
+            prims,
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            factor: f,
+            factor_f: factor,
+            factor2: f2,
+            factor2_f: factor2,
+            bounds: None,
         }
     }
+    pub fn set_bounds(&mut self, center: [f64; 3], radius: f64) {
+        self.bounds = Some((center, radius));
+    }
+    fn new_untransformed(
+        group_primitive_type: GroupModifierType,
+        prims: Vec<Box<dyn Primitive>>,
+        factor: f64!(),
+    ) -> GroupPrimitive {
+        GroupPrimitive::new(
+            group_primitive_type,
+            prims,
+            factor,
+            f64!(0.0),
+            f64v!([0.0, 0.0, 0.0]),
+            f64v!([0.0, 0.0, 0.0]),
+            f64v!([1.0, 1.0, 1.0]),
+            Vec::new(),
+            Vec::new(),
+        )
+    }
+    pub fn new_union(prims: Vec<Box<dyn Primitive>>) -> GroupPrimitive {
+        GroupPrimitive::new_untransformed(GroupModifierType::Union, prims, f64!(0.0))
+    }
     pub fn new_subtraction(prims: Vec<Box<dyn Primitive>>) -> GroupPrimitive {
-        GroupPrimitive {
-            group_primitive_type: GroupModifierType::Subtraction,
-            prims: prims,
-            primtive_data: get_primitive_data(
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([1.0, 1.0, 1.0]),
-                Vec::new(),
-            ),
-            factor_f: f64!(0.0),
-            factor: 0.0,
-        }
+        GroupPrimitive::new_untransformed(GroupModifierType::Subtraction, prims, f64!(0.0))
     }
     pub fn new_intersection(prims: Vec<Box<dyn Primitive>>) -> GroupPrimitive {
-        GroupPrimitive {
-            group_primitive_type: GroupModifierType::Intersection,
-            prims: prims,
-            primtive_data: get_primitive_data(
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([1.0, 1.0, 1.0]),
-                Vec::new(),
-            ),
-            factor_f: f64!(0.0),
-            factor: 0.0,
-        }
+        GroupPrimitive::new_untransformed(GroupModifierType::Intersection, prims, f64!(0.0))
     }
     pub fn new_union_smooth(prims: Vec<Box<dyn Primitive>>, factor: f64!()) -> GroupPrimitive {
-        let f = get_f64!(factor);
-        GroupPrimitive {
-            group_primitive_type: GroupModifierType::SmoothUnion,
-            prims: prims,
-            primtive_data: get_primitive_data(
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([1.0, 1.0, 1.0]),
-                Vec::new(),
-            ),
-            factor: f,
-            factor_f: factor,
-        }
+        GroupPrimitive::new_untransformed(GroupModifierType::SmoothUnion, prims, factor)
     }
     pub fn new_subtraction_smooth(
         prims: Vec<Box<dyn Primitive>>,
         factor: f64!(),
     ) -> GroupPrimitive {
-        let f = get_f64!(factor);
-        GroupPrimitive {
-            group_primitive_type: GroupModifierType::SmoothSubtraction,
-            prims: prims,
-            primtive_data: get_primitive_data(
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([1.0, 1.0, 1.0]),
-                Vec::new(),
-            ),
-            factor: f,
-            factor_f: factor,
-        }
+        GroupPrimitive::new_untransformed(GroupModifierType::SmoothSubtraction, prims, factor)
     }
     pub fn new_intersection_smooth(
         prims: Vec<Box<dyn Primitive>>,
         factor: f64!(),
     ) -> GroupPrimitive {
-        let f = get_f64!(factor);
-        GroupPrimitive {
-            group_primitive_type: GroupModifierType::SmoothIntersection,
-            prims: prims,
-            primtive_data: get_primitive_data(
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([0.0, 0.0, 0.0]),
-                f64v!([1.0, 1.0, 1.0]),
-                Vec::new(),
-            ),
-            factor: f,
-            factor_f: factor,
+        GroupPrimitive::new_untransformed(GroupModifierType::SmoothIntersection, prims, factor)
+    }
+
+    // Folds all children with a two distance operator, the material and fractal data are taken from the child
+    // selected by pick
+    fn blend(
+        &self,
+        first: PrimitiveResult,
+        pos: [f64; 3],
+        op: impl Fn(f64, f64) -> f64,
+        pick: BlendPick,
+        negate_first: bool,
+    ) -> PrimitiveResult {
+        let mut distance = first;
+        if negate_first {
+            distance.distance = -distance.distance;
+        }
+        for p in 1..self.prims.len() {
+            let cur_dist = self.prims[p].map_primitive(pos);
+            let d = op(distance.distance, cur_dist.distance);
+            let take = match pick {
+                BlendPick::Closer => cur_dist.distance < distance.distance,
+                BlendPick::Further => cur_dist.distance > distance.distance,
+                BlendPick::First => false,
+            };
+            if take {
+                distance = cur_dist;
+            }
+            distance.distance = d;
         }
+        distance
     }
 }
 
 impl InternalPrimitive for GroupPrimitive {
     fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        if let Some((center, radius)) = self.bounds {
+            // Smooth unions grow by at most a quarter of their factor, the other blends stay within the factor
+            let margin = match self.group_primitive_type {
+                GroupModifierType::SmoothUnion => 0.25 * self.factor.abs(),
+                _ => self.factor.abs(),
+            };
+            let bound_dist = vecmath::vec3_len(vecmath::vec3_sub(pos, center)) - radius - margin;
+            if bound_dist > GROUP_BOUNDS_MARGIN {
+                // The children lie inside the bounds, so the distance to them is a lower bound
+                let mut result = self.prims[0].map_primitive(pos);
+                result.distance = bound_dist;
+                return result;
+            }
+        }
         let mut distance = self.prims[0].map_primitive(pos);
         // if(!distance.distance.is_nan()){
 
@@ -338,6 +633,8 @@ impl InternalPrimitive for GroupPrimitive {
                     if h > 1.0 {
                         h = 1.0;
                     }
+                    distance.material = MaterialRaw::mix(cur_dist.material, distance.material, h);
+                    distance.fractal_data = mix_fractal_data(cur_dist.fractal_data, distance.fractal_data, h);
                     distance.distance = helpers::mix(cur_dist.distance, distance.distance, h)
                         - self.factor * h * (1.0 - h);
                 }
@@ -352,6 +649,8 @@ impl InternalPrimitive for GroupPrimitive {
                     if h > 1.0 {
                         h = 1.0;
                     }
+                    distance.material = MaterialRaw::mix(cur_dist.material, distance.material, h);
+                    distance.fractal_data = mix_fractal_data(cur_dist.fractal_data, distance.fractal_data, h);
                     distance.distance = helpers::mix(cur_dist.distance, -distance.distance, h)
                         + self.factor * h * (1.0 - h);
                 }
@@ -366,10 +665,65 @@ impl InternalPrimitive for GroupPrimitive {
                     if h > 1.0 {
                         h = 1.0;
                     }
+                    distance.material = MaterialRaw::mix(cur_dist.material, distance.material, h);
+                    distance.fractal_data = mix_fractal_data(cur_dist.fractal_data, distance.fractal_data, h);
                     distance.distance = helpers::mix(cur_dist.distance, distance.distance, h)
                         + self.factor * h * (1.0 - h);
                 }
             }
+            // Subtractions remove the first child from the others, like the hard subtraction
+            GroupModifierType::ChamferUnion => {
+                distance = self.blend(distance, pos, |a, b| csg::union_chamfer(a, b, self.factor), BlendPick::Closer, false);
+            }
+            GroupModifierType::ChamferSubtraction => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_chamfer(a, b, self.factor), BlendPick::Further, true);
+            }
+            GroupModifierType::ChamferIntersection => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_chamfer(a, b, self.factor), BlendPick::Further, false);
+            }
+            GroupModifierType::RoundUnion => {
+                distance = self.blend(distance, pos, |a, b| csg::union_round(a, b, self.factor), BlendPick::Closer, false);
+            }
+            GroupModifierType::RoundSubtraction => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_round(a, b, self.factor), BlendPick::Further, true);
+            }
+            GroupModifierType::RoundIntersection => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_round(a, b, self.factor), BlendPick::Further, false);
+            }
+            GroupModifierType::ColumnsUnion => {
+                distance = self.blend(distance, pos, |a, b| csg::union_columns(a, b, self.factor, self.factor2), BlendPick::Closer, false);
+            }
+            GroupModifierType::ColumnsSubtraction => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_columns(a, b, self.factor, self.factor2), BlendPick::Further, true);
+            }
+            GroupModifierType::ColumnsIntersection => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_columns(a, b, self.factor, self.factor2), BlendPick::Further, false);
+            }
+            GroupModifierType::StairsUnion => {
+                distance = self.blend(distance, pos, |a, b| csg::union_stairs(a, b, self.factor, self.factor2), BlendPick::Closer, false);
+            }
+            GroupModifierType::StairsSubtraction => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_stairs(a, b, self.factor, self.factor2), BlendPick::Further, true);
+            }
+            GroupModifierType::StairsIntersection => {
+                distance = self.blend(distance, pos, |a, b| csg::intersection_stairs(a, b, self.factor, self.factor2), BlendPick::Further, false);
+            }
+            // The first child is the base, all others are cut into or added onto it
+            GroupModifierType::Groove => {
+                distance = self.blend(distance, pos, |a, b| csg::groove(a, b, self.factor, self.factor2), BlendPick::First, false);
+            }
+            GroupModifierType::Tongue => {
+                distance = self.blend(distance, pos, |a, b| csg::tongue(a, b, self.factor, self.factor2), BlendPick::First, false);
+            }
+            GroupModifierType::Engrave => {
+                distance = self.blend(distance, pos, |a, b| csg::engrave(a, b, self.factor), BlendPick::First, false);
+            }
+            GroupModifierType::ExpSmoothUnion => {
+                distance = self.blend(distance, pos, |a, b| csg::smooth_union_exp(a, b, self.factor), BlendPick::Closer, false);
+            }
+            GroupModifierType::PowSmoothUnion => {
+                distance = self.blend(distance, pos, |a, b| csg::smooth_union_pow(a, b, self.factor), BlendPick::Closer, false);
+            }
         }
         distance
     }
@@ -385,9 +739,10 @@ impl InternalPrimitive for GroupPrimitive {
             p.evaluate(t);
         }
         self.factor = get_ref_f64!(self.factor_f);
+        self.factor2 = get_ref_f64!(self.factor2_f);
     }
     fn _use_raw_position(&self) -> bool {
-        return true;
+        false
     }
 }
 
@@ -408,18 +763,14 @@ impl Sphere {
         rot: [f64!(); 3],
         scale: [f64!(); 3],
         pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
         material: Option<Material>,
     ) -> Sphere {
         let m = material.unwrap_or(Material::new_direct());
         Sphere {
             rad,
-            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier),
-            material_raw: MaterialRaw::new(
-                m.albedo.clone(),
-                m.specular.clone(),
-                m.diffuse.clone(),
-                m.n_specular.clone(),
-            ),
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            material_raw: MaterialRaw::new(&m),
             material: m,
         }
     }
@@ -430,7 +781,7 @@ impl InternalPrimitive for Sphere {
         let distance = vecmath::vec3_len(pos) - get_f64!(self.rad);
         PrimitiveResult {
             distance,
-            fractal_data: [0.0, 0.0, 0.0, 0.0],
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
             material: self.material_raw
         }
     }
@@ -466,19 +817,15 @@ impl Torus {
         rot: [f64!(); 3],
         scale: [f64!(); 3],
         pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
         material: Option<Material>,
     ) -> Torus {
         let m = material.unwrap_or(Material::new_direct());
         Torus {
             ring_rad,
             rad,
-            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier),
-            material_raw: MaterialRaw::new(
-                m.albedo.clone(),
-                m.specular.clone(),
-                m.diffuse.clone(),
-                m.n_specular.clone(),
-            ),
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            material_raw: MaterialRaw::new(&m),
             material: m,
         }
     }
@@ -490,7 +837,7 @@ impl InternalPrimitive for Torus {
         let distance = (l * l + pos[1] * pos[1]).sqrt() - get_f64!(self.ring_rad);
         PrimitiveResult {
             distance,
-            fractal_data: [0.0, 0.0, 0.0, 0.0],
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
             material: self.material_raw
         }
     }
@@ -523,18 +870,14 @@ impl Cube {
         rot: [f64!(); 3],
         scale: [f64!(); 3],
         pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
         material: Option<Material>,
     ) -> Cube {
         let m = material.unwrap_or(Material::new_direct());
         Cube {
             bounds,
-            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier),
-            material_raw: MaterialRaw::new(
-                m.albedo.clone(),
-                m.specular.clone(),
-                m.diffuse.clone(),
-                m.n_specular.clone(),
-            ),
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            material_raw: MaterialRaw::new(&m),
             material: m,
         }
     }
@@ -553,7 +896,7 @@ impl InternalPrimitive for Cube {
         ) + vecmath::vec3_len(helpers::vec_f_max(dist_vec, 0.0));
         PrimitiveResult {
             distance,
-            fractal_data: [0.0, 0.0, 0.0, 0.0],
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
             material: self.material_raw
         }
     }
@@ -587,17 +930,13 @@ impl Mandelbulb {
         rot: [f64!(); 3],
         scale: [f64!(); 3],
         pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
     ) -> Mandelbulb {
         let m = Material::new_direct();
         Mandelbulb {
-            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier),
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
             power: power,
-            material_raw: MaterialRaw::new(
-                m.albedo.clone(),
-                m.specular.clone(),
-                m.diffuse.clone(),
-                m.n_specular.clone(),
-            ),
+            material_raw: MaterialRaw::new(&m),
             material: m,
         }
     }
@@ -610,6 +949,7 @@ impl InternalPrimitive for Mandelbulb {
         let mut r = 0.0;
         let mut iterations = 0;
         let pow = *self.power.as_ref().borrow();
+        let mut trap = [z[0].abs(), z[1].abs(), z[2].abs(), vecmath::vec3_dot(z, z)];
 
         for i in 0..15 {
             iterations = i;
@@ -633,16 +973,23 @@ impl InternalPrimitive for Mandelbulb {
                 theta.cos() * zr,
             ];
             z = [z[0] + pos[0], z[1] + pos[1], z[2] + pos[2]];
+
+            trap = [
+                min_f64(trap[0], z[0].abs()),
+                min_f64(trap[1], z[1].abs()),
+                min_f64(trap[2], z[2].abs()),
+                min_f64(trap[3], vecmath::vec3_dot(z, z)),
+            ];
         }
         let distance = 0.5 * r.log(std::f64::consts::E) * r / dr;
+        let smooth_iterations = if r > 2.0 {
+            iterations as f64 + 1.0 - (r.ln() / 2.0_f64.ln()).ln() / pow.ln()
+        } else {
+            iterations as f64 + 1.0
+        };
         PrimitiveResult {
             distance,
-            fractal_data: [
-                iterations as f64,
-                iterations as f64,
-                iterations as f64,
-                iterations as f64,
-            ],
+            fractal_data: [trap[0], trap[1], trap[2], trap[3], smooth_iterations],
             material: self.material_raw
         }
     }
@@ -676,17 +1023,13 @@ impl Julia {
         rot: [f64!(); 3],
         scale: [f64!(); 3],
         pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
     ) -> Julia {
         let m = Material::new_direct();
         Julia {
-            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier),
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
             power: power,
-            material_raw: MaterialRaw::new(
-                m.albedo.clone(),
-                m.specular.clone(),
-                m.diffuse.clone(),
-                m.n_specular.clone(),
-            ),
+            material_raw: MaterialRaw::new(&m),
             material: m,
         }
     }
@@ -732,9 +1075,14 @@ impl InternalPrimitive for Julia {
             n += 1.0
         }
 
+        let smooth_iterations = if mz2 > 4.0 {
+            n + 1.0 - (0.5 * mz2.ln() / 2.0_f64.ln()).ln() / 2.0_f64.ln()
+        } else {
+            n
+        };
         PrimitiveResult {
             distance: 0.25 * (mz2 / md2).sqrt() * mz2.log(std::f64::consts::E),
-            fractal_data: trap,
+            fractal_data: [trap[0], trap[1], trap[2], trap[3], smooth_iterations],
             material: self.material_raw
         }
     }
@@ -749,3 +1097,754 @@ impl InternalPrimitive for Julia {
         return false;
     }
 }
+
+// ------------------------------------------
+//                  Mesh
+// ------------------------------------------
+
+pub struct Mesh {
+    primtive_data: PrimitiveData,
+    mesh: mesh::TriangleMesh,
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Mesh {
+    pub fn new(
+        path: &str,
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Mesh {
+        let m = material.unwrap_or(Material::new_direct());
+        Mesh {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            mesh: mesh::load_mesh(path),
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for Mesh {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        PrimitiveResult {
+            distance: self.mesh.signed_distance(pos),
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {}
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//               Baked Grid
+// ------------------------------------------
+
+pub struct BakedPrimitive {
+    primtive_data: PrimitiveData,
+    grid: distance_grid::DistanceGrid,
+    interpolation: distance_grid::Interpolation,
+    // Evaluated exactly closer to the surface than one grid cell, if available
+    source: Option<Box<dyn Primitive>>,
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl BakedPrimitive {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        grid: distance_grid::DistanceGrid,
+        interpolation: distance_grid::Interpolation,
+        source: Option<Box<dyn Primitive>>,
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> BakedPrimitive {
+        let m = material.unwrap_or(Material::new_direct());
+        BakedPrimitive {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            grid,
+            interpolation,
+            source,
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for BakedPrimitive {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let bounds_dist = self.grid.bounds_distance(pos);
+        // The baked object lies inside the bounds, so the distance to them is a lower bound
+        let distance = if bounds_dist > 0.0 { bounds_dist } else { self.grid.sample(pos, self.interpolation) };
+
+        // The exact SDF is only needed beyond the resolution of the grid
+        if bounds_dist <= 0.0 && distance.abs() < self.grid.cell_size() {
+            if let Some(source) = &self.source {
+                return source.map_primitive(pos);
+            }
+        }
+        PrimitiveResult {
+            distance,
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {
+        if let Some(source) = &mut self.source {
+            source.evaluate(t);
+        }
+    }
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//               Heightfield
+// ------------------------------------------
+
+// Smaller extents are clamped, a zero extent would divide by zero
+const MIN_HEIGHTFIELD_EXTENT: f64 = 1e-6;
+
+pub struct Heightfield {
+    primtive_data: PrimitiveData,
+    source: heightfield::HeightSource,
+    height: f64!(),
+    extent: [f64!(); 2],
+    step_factor: f64,
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Heightfield {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        source: heightfield::HeightSource,
+        height: f64!(),
+        extent: [f64!(); 2],
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Heightfield {
+        let m = material.unwrap_or(Material::new_direct());
+        let mut heightfield = Heightfield {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            source,
+            height,
+            extent,
+            step_factor: 1.0,
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        };
+        heightfield.update_step_factor();
+        heightfield
+    }
+
+    // The vertical distance to the surface overestimates on slopes, scaling by the steepest slope keeps it conservative
+    fn update_step_factor(&mut self) {
+        let extent = helpers::min_f64(get_ref_f64!(self.extent[0]), get_ref_f64!(self.extent[1])).max(MIN_HEIGHTFIELD_EXTENT);
+        let slope = get_ref_f64!(self.height).abs() * self.source.max_gradient() / extent;
+        self.step_factor = 1.0 / (1.0 + slope * slope).sqrt();
+    }
+}
+
+impl InternalPrimitive for Heightfield {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let height = get_f64!(self.height);
+        let extent = [
+            get_f64!(self.extent[0]).max(MIN_HEIGHTFIELD_EXTENT),
+            get_f64!(self.extent[1]).max(MIN_HEIGHTFIELD_EXTENT),
+        ];
+
+        let terrain = self.source.sample(pos[0] / extent[0] + 0.5, pos[2] / extent[1] + 0.5) * height;
+        let height_dist = (pos[1] - terrain) * self.step_factor;
+
+        // The terrain is a column over its extent, open to the bottom
+        let q = [pos[0].abs() - 0.5 * extent[0], pos[2].abs() - 0.5 * extent[1]];
+        let column_dist = min_f64(helpers::max_f64(q[0], q[1]), 0.0)
+            + (helpers::max_f64(q[0], 0.0).powi(2) + helpers::max_f64(q[1], 0.0).powi(2)).sqrt();
+
+        let distance = helpers::max_f64(helpers::max_f64(height_dist, column_dist), pos[1] - height);
+        PrimitiveResult {
+            distance,
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {
+        self.update_step_factor();
+    }
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//                  Tube
+// ------------------------------------------
+
+const TUBE_SAMPLES_PER_SPAN: usize = 16;
+
+pub struct Tube {
+    primtive_data: PrimitiveData,
+    control_points: Vec<[f64!(); 3]>,
+    curve_type: curve::CurveType,
+    // Radius at the start and the end of the curve
+    radius: [f64!(); 2],
+    // Fraction of the curve that is visible, for growth animations
+    grow: f64!(),
+    polyline: Vec<[f64; 3]>,
+    // Normalized arc length at every polyline point
+    arc_length: Vec<f64>,
+    bounds: ([f64; 3], [f64; 3]),
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Tube {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        control_points: Vec<[f64!(); 3]>,
+        curve_type: curve::CurveType,
+        radius: [f64!(); 2],
+        grow: f64!(),
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Tube {
+        if control_points.is_empty() {
+            panic!("Tubes need at least one control point");
+        }
+        let m = material.unwrap_or(Material::new_direct());
+        let mut tube = Tube {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            control_points,
+            curve_type,
+            radius,
+            grow,
+            polyline: Vec::new(),
+            arc_length: Vec::new(),
+            bounds: ([0.0; 3], [0.0; 3]),
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        };
+        tube.update_curve();
+        tube
+    }
+
+    fn update_curve(&mut self) {
+        let points: Vec<[f64; 3]> = self.control_points.iter().map(|p| get_ref_f64v!(p)).collect();
+        self.polyline = curve::sample_curve(&points, self.curve_type, TUBE_SAMPLES_PER_SPAN);
+
+        let mut total = 0.0;
+        self.arc_length = vec![0.0];
+        for i in 1..self.polyline.len() {
+            total += vecmath::vec3_len(vecmath::vec3_sub(self.polyline[i], self.polyline[i - 1]));
+            self.arc_length.push(total);
+        }
+        if total > 0.0 {
+            for l in self.arc_length.iter_mut() {
+                *l /= total;
+            }
+        }
+
+        let r = helpers::max_f64(get_ref_f64!(self.radius[0]), get_ref_f64!(self.radius[1]));
+        let mut min = [f64::MAX; 3];
+        let mut max = [f64::MIN; 3];
+        for p in &self.polyline {
+            for k in 0..3 {
+                min[k] = min_f64(min[k], p[k] - r);
+                max[k] = helpers::max_f64(max[k], p[k] + r);
+            }
+        }
+        self.bounds = (min, max);
+    }
+}
+
+impl InternalPrimitive for Tube {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let mut distance = f64::MAX;
+
+        // Skips the segments as long as the ray is far away from the tube
+        let mut box_dist2 = 0.0;
+        for ((min, max), p) in self.bounds.0.iter().zip(self.bounds.1).zip(pos) {
+            let d = helpers::max_f64(helpers::max_f64(min - p, 0.0), p - max);
+            box_dist2 += d * d;
+        }
+        let grow = get_f64!(self.grow).clamp(0.0, 1.0);
+        if box_dist2 > 1.0 || self.polyline.len() < 2 || grow <= 0.0 {
+            distance = box_dist2.sqrt();
+        } else {
+            let radius = [get_f64!(self.radius[0]), get_f64!(self.radius[1])];
+            for i in 0..self.polyline.len() - 1 {
+                let s_a = self.arc_length[i];
+                if s_a >= grow {
+                    break;
+                }
+                let a = self.polyline[i];
+                let mut b = self.polyline[i + 1];
+                let mut s_b = self.arc_length[i + 1];
+                if s_b > grow {
+                    b = helpers::vec_interpolate(a, b, (grow - s_a) / (s_b - s_a));
+                    s_b = grow;
+                }
+
+                let ab = vecmath::vec3_sub(b, a);
+                let ap = vecmath::vec3_sub(pos, a);
+                let len2 = vecmath::vec3_dot(ab, ab);
+                let h = if len2 > 0.0 { (vecmath::vec3_dot(ap, ab) / len2).clamp(0.0, 1.0) } else { 0.0 };
+                let closest = vecmath::vec3_add(a, vecmath::vec3_scale(ab, h));
+                let r = helpers::mix(radius[0], radius[1], helpers::mix(s_a, s_b, h));
+                distance = min_f64(distance, vecmath::vec3_len(vecmath::vec3_sub(pos, closest)) - r);
+            }
+        }
+
+        PrimitiveResult {
+            distance,
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {
+        self.update_curve();
+    }
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//                Extrusion
+// ------------------------------------------
+
+pub struct Extrusion {
+    primtive_data: PrimitiveData,
+    shape: Box<dyn sdf2d::Shape2D>,
+    // The shape lies in the plane of the other two axes
+    axis: usize,
+    // Half of the extruded length
+    depth: f64!(),
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Extrusion {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        shape: Box<dyn sdf2d::Shape2D>,
+        axis: usize,
+        depth: f64!(),
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Extrusion {
+        let m = material.unwrap_or(Material::new_direct());
+        Extrusion {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            shape,
+            axis: axis.min(2),
+            depth,
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for Extrusion {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let u = (self.axis + 1) % 3;
+        let v = (self.axis + 2) % 3;
+        let d = self.shape.distance([pos[u], pos[v]]);
+        let w = [d, pos[self.axis].abs() - get_f64!(self.depth)];
+        let distance = min_f64(helpers::max_f64(w[0], w[1]), 0.0)
+            + (helpers::max_f64(w[0], 0.0).powi(2) + helpers::max_f64(w[1], 0.0).powi(2)).sqrt();
+        PrimitiveResult {
+            distance,
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {}
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//                Revolution
+// ------------------------------------------
+
+pub struct Revolution {
+    primtive_data: PrimitiveData,
+    shape: Box<dyn sdf2d::Shape2D>,
+    // The shape's x axis points away from this axis, its y axis along it
+    axis: usize,
+    // Distance of the shape's origin from the axis
+    offset: f64!(),
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Revolution {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        shape: Box<dyn sdf2d::Shape2D>,
+        axis: usize,
+        offset: f64!(),
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Revolution {
+        let m = material.unwrap_or(Material::new_direct());
+        Revolution {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            shape,
+            axis: axis.min(2),
+            offset,
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for Revolution {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let u = (self.axis + 1) % 3;
+        let v = (self.axis + 2) % 3;
+        let radial = (pos[u] * pos[u] + pos[v] * pos[v]).sqrt() - get_f64!(self.offset);
+        PrimitiveResult {
+            distance: self.shape.distance([radial, pos[self.axis]]),
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {}
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//                 Scripted
+// ------------------------------------------
+
+pub struct ScriptedPrimitive {
+    primtive_data: PrimitiveData,
+    // Defines fn sdf(p, t) returning the distance or [distance, fractal_data0, ..., fractal_data4]
+    script: script::Script,
+    time: f64,
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl ScriptedPrimitive {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        path: &str,
+        params: Vec<f64!()>,
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> ScriptedPrimitive {
+        let script = script::Script::load(path, params);
+        if !script.has_fn("sdf", 2) {
+            panic!("Script {} has no fn sdf(p, t)", path);
+        }
+        let m = material.unwrap_or(Material::new_direct());
+        ScriptedPrimitive {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            script,
+            time: 0.0,
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for ScriptedPrimitive {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let res = script::dynamic_to_vec(self.script.call(
+            "sdf",
+            vec![script::vec3_to_dynamic(pos), rhai::Dynamic::from_float(self.time)],
+        ));
+        let mut fractal_data = [0.0, 0.0, 0.0, 0.0, 0.0];
+        for (i, v) in res.iter().skip(1).take(5).enumerate() {
+            fractal_data[i] = *v;
+        }
+        PrimitiveResult {
+            distance: res.first().copied().unwrap_or(f64::MAX),
+            fractal_data,
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {
+        self.time = t;
+    }
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//                Instancer
+// ------------------------------------------
+
+pub struct Instancer {
+    primtive_data: PrimitiveData,
+    prototype: Box<dyn Primitive>,
+    instances: instancer::InstanceSet,
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Instancer {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        prototype: Box<dyn Primitive>,
+        // Bounding sphere radius of the prototype around its origin
+        radius: f64,
+        instances: &[instancer::InstanceDesc],
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Instancer {
+        let m = material.unwrap_or(Material::new_direct());
+        Instancer {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            prototype,
+            instances: instancer::InstanceSet::new(instances, radius),
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for Instancer {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let (distance, result) = self.instances.closest(pos, &|i, local| {
+            let mut res = self.prototype.map_primitive(local);
+            // The instance transformation lies between the instancer and the prototype
+            if let Some(space) = res.material.texture_space.as_mut() {
+                space.frame = vecmath::row_mat3_mul(self.instances.normal_frame(i), space.frame);
+            }
+            let m = self.instances.material(i);
+            if let Some(shading) = m.shading {
+                res.material.albedo = shading[0];
+                res.material.specular = shading[1];
+                res.material.diffuse = shading[2];
+                res.material.n_specular = shading[3];
+            }
+            if let Some(color) = m.color {
+                res.material.color = color;
+            }
+            if let Some(metallic) = m.metallic {
+                res.material.metallic = metallic;
+            }
+            if let Some(roughness) = m.roughness {
+                res.material.roughness = roughness;
+            }
+            (res.distance, res)
+        });
+        match result {
+            Some(mut res) => {
+                res.distance = distance;
+                res
+            }
+            None => PrimitiveResult {
+                distance,
+                fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+                material: self.material_raw
+            },
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {
+        self.prototype.evaluate(t);
+    }
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
+
+// ------------------------------------------
+//                 Capsule
+// ------------------------------------------
+
+// A round cone between a and b. Only the part up to growth - growth_start of the way to b is shown, so a
+// shared growth value can reveal a chain of capsules one after another.
+pub struct Capsule {
+    primtive_data: PrimitiveData,
+    a: [f64!(); 3],
+    b: [f64!(); 3],
+    radius: [f64!(); 2],
+    growth: f64!(),
+    growth_start: f64,
+    material: Material,
+    material_raw: MaterialRaw,
+}
+
+impl Capsule {
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        a: [f64!(); 3],
+        b: [f64!(); 3],
+        radius: [f64!(); 2],
+        growth: f64!(),
+        growth_start: f64,
+        pos: [f64!(); 3],
+        rot: [f64!(); 3],
+        scale: [f64!(); 3],
+        pos_modifier: Vec<Box<dyn modifier::PosModifier>>,
+        dist_modifier: Vec<Box<dyn modifier::DistModifier>>,
+        material: Option<Material>,
+    ) -> Capsule {
+        let m = material.unwrap_or(Material::new_direct());
+        Capsule {
+            primtive_data: get_primitive_data(pos, rot, scale, pos_modifier, dist_modifier),
+            a,
+            b,
+            radius,
+            growth,
+            growth_start,
+            material_raw: MaterialRaw::new(&m),
+            material: m,
+        }
+    }
+}
+
+impl InternalPrimitive for Capsule {
+    fn _map_primitive(&self, pos: [f64; 3]) -> PrimitiveResult {
+        let reveal = (get_f64!(self.growth) - self.growth_start).clamp(0.0, 1.0);
+        if reveal <= 0.0 {
+            return PrimitiveResult {
+                distance: f64::MAX,
+                fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+                material: self.material_raw
+            };
+        }
+
+        let a = get_f64v!(self.a);
+        let b_full = get_f64v!(self.b);
+        let b = vecmath::vec3_add(a, vecmath::vec3_scale(vecmath::vec3_sub(b_full, a), reveal));
+        let r1 = get_f64!(self.radius[0]);
+        let r2 = helpers::mix(r1, get_f64!(self.radius[1]), reveal);
+
+        // Round cone, from Inigo Quilez
+        let ba = vecmath::vec3_sub(b, a);
+        let pa = vecmath::vec3_sub(pos, a);
+        let l2 = vecmath::vec3_dot(ba, ba);
+        let distance = if l2 < 1e-12 {
+            vecmath::vec3_len(pa) - helpers::max_f64(r1, r2)
+        } else {
+            let rr = r1 - r2;
+            let a2 = l2 - rr * rr;
+            let il2 = 1.0 / l2;
+            let y = vecmath::vec3_dot(pa, ba);
+            let z = y - l2;
+            let x = vecmath::vec3_sub(vecmath::vec3_scale(pa, l2), vecmath::vec3_scale(ba, y));
+            let x2 = vecmath::vec3_dot(x, x);
+            let y2 = y * y * l2;
+            let z2 = z * z * l2;
+            let k = rr.signum() * rr * rr * x2;
+            if z.signum() * a2 * z2 > k {
+                (x2 + z2).sqrt() * il2 - r2
+            } else if y.signum() * a2 * y2 < k {
+                (x2 + y2).sqrt() * il2 - r1
+            } else {
+                ((x2 * a2 * il2).sqrt() + y * rr) * il2 - r1
+            }
+        };
+
+        PrimitiveResult {
+            distance,
+            fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0],
+            material: self.material_raw
+        }
+    }
+    fn get_primitive_data(&self) -> &PrimitiveData {
+        &self.primtive_data
+    }
+    fn get_primitive_data_mut(&mut self) -> &mut PrimitiveData {
+        &mut self.primtive_data
+    }
+    fn _evaluate(&mut self, t: f64) {}
+    fn _use_raw_position(&self) -> bool {
+        false
+    }
+}
@@ -1,19 +1,46 @@
+use crate::brdf;
 use crate::cameras;
+use crate::environment;
 use crate::film;
-use crate::helpers;
-use crate::helpers::max_f64;
 use crate::sampler::Sampler;
 use crate::solver;
 use crate::shader;
 use crate::configuration;
+use crate::evaluator;
+use crate::helpers;
 use crate::light;
-use std::{rc::Rc, cell::RefCell};
+use std::{rc::Rc, cell::RefCell, f64::consts::PI};
 
 pub trait Renderer{
     fn render(&mut self);
     fn prepare_render(&mut self);
     fn save_image(&self, path: &str);
     fn evaluate(&mut self, t: f64);
+    // Offsets in frames at which the scene is rendered, render() weights every pass accordingly
+    fn shutter_times(&self) -> Vec<f64>{
+        vec![0.0]
+    }
+    // Length in frames of the shutter stratum that starts at every shutter time
+    fn shutter_stratum(&self) -> f64{
+        0.0
+    }
+    // Evaluates everything that moves during a pass at the end of its stratum
+    fn evaluate_end(&mut self, t: f64){
+    }
+
+    // Evaluates the scene at every shutter time and accumulates all passes in the film
+    fn render_frame(&mut self, t: f64){
+        self.prepare_render();
+        let stratum = self.shutter_stratum() / configuration::ups;
+        for offset in self.shutter_times(){
+            let time = t + offset / configuration::ups;
+            evaluator::evaluate(time + stratum);
+            self.evaluate_end(time + stratum);
+            evaluator::evaluate(time);
+            self.evaluate(time);
+            self.render();
+        }
+    }
 }
 
 pub struct CameraRayRenderer<C: cameras::Camera, F: film::Film> {
@@ -25,7 +52,7 @@ impl<C: cameras::Camera, F: film::Film> Renderer for CameraRayRenderer<C, F>{
     fn render(&mut self){
         for x in 0..configuration::width{
             for y in 0..configuration::height{
-                let ray = self.camera.generate_ray(x as f64, y as f64, ((0.0,0.0),(0.0,0.0)));
+                let ray = self.camera.generate_ray(x as f64, y as f64, ((0.0,0.0),(0.0,0.0),0.0));
                 self.film.write_pixel(x, y, ray.0);
             }
         }
@@ -65,7 +92,10 @@ pub struct SolverRenderer<C: cameras::Camera, F: film::Film, S: solver::Solver,
     film: F,
     solver: S,
     shader: H,
-    sampler: A
+    sampler: A,
+    glow: Option<light::Glow>,
+    // Marks pixels whose ray overshot the surface in magenta
+    debug_overshoot: bool
 }
 
 fn generate_samples() -> [[f64;2];(configuration::samples*configuration::samples) as usize]{
@@ -84,19 +114,28 @@ impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A:
             panic!("Solver Renderer does not support lit shaders!");
         }
 
-        let sample_inv = 1.0 / ((configuration::samples * configuration::samples) as f64);
+        let sample_inv = 1.0 / ((configuration::samples * configuration::samples * self.sampler.time_samples()) as f64);
         for x in 0..configuration::width{
             for y in 0..configuration::height{
                 for s in self.sampler.generate_samples(x, y) {
                     let ray = self.camera.generate_ray(x as f64, y as f64, s);
                     // let ray = self.camera.generate_ray(x as f64, y as f64);
                     let i = self.solver.solve(ray);
+                    if self.debug_overshoot && i.overshoot {
+                        self.film.write_pixel(x, y, [sample_inv, 0.0, sample_inv]);
+                        continue;
+                    }
                     if i.hit {
                         let col = self.shader.surface_props(x, y, &i);
-                        self.film.write_pixel(x, y, [col.color[0] * sample_inv, col.color[1] * sample_inv, col.color[2] * sample_inv]);
+                        let emitted = i.material.emitted();
+                        self.film.write_pixel(x, y, [(col.color[0] + emitted[0]) * sample_inv, (col.color[1] + emitted[1]) * sample_inv, (col.color[2] + emitted[2]) * sample_inv]);
                     }else{
+                        let glow = match &self.glow {
+                            Some(g) => g.glow(i.min_dist, i.min_dist_step),
+                            None => [0.0, 0.0, 0.0],
+                        };
                         let col = self.shader.miss_color(x, y, i);
-                        self.film.write_pixel(x, y, [col[0] * sample_inv, col[1] * sample_inv, col[2] * sample_inv]);
+                        self.film.write_pixel(x, y, [(col[0] + glow[0]) * sample_inv, (col[1] + glow[1]) * sample_inv, (col[2] + glow[2]) * sample_inv]);
                     }
                 }
             }
@@ -115,18 +154,33 @@ impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A:
         self.solver.evaluate(t);
         self.shader.evaluate(t);
     }
+    fn shutter_times(&self) -> Vec<f64>{
+        self.sampler.generate_times()
+    }
+    fn shutter_stratum(&self) -> f64{
+        self.sampler.stratum()
+    }
+    fn evaluate_end(&mut self, t: f64){
+        self.camera.evaluate_end(t);
+    }
 }
 
 impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A: Sampler> SolverRenderer<C, F, S, H, A>{
-    pub fn new(camera: C, film: F, solver: S, shader: H, sampler: A) -> SolverRenderer<C, F, S, H, A>{
+    pub fn new(camera: C, film: F, solver: S, shader: H, sampler: A, glow: Option<light::Glow>) -> SolverRenderer<C, F, S, H, A>{
         SolverRenderer{
             camera: camera,
             film: film,
             solver,
             shader,
-            sampler
+            sampler,
+            glow,
+            debug_overshoot: configuration::debug_overshoot
         }
     }
+
+    pub fn set_debug_overshoot(&mut self, debug_overshoot: bool){
+        self.debug_overshoot = debug_overshoot;
+    }
 }
 
 
@@ -145,7 +199,12 @@ pub struct LightRenderer<C: cameras::Camera, F: film::Film, S: solver::Solver, H
     shader: H,
     sampler: A,
     lights: Vec<Box<dyn light::Light>>,
-    ambient: [f64!(); 3]
+    ambient: [f64!(); 3],
+    glow: Option<light::Glow>,
+    // Background of missed rays and image based ambient light
+    environment: Option<environment::EnvironmentMap>,
+    // Marks pixels whose ray overshot the surface in magenta
+    debug_overshoot: bool
 }
 
 
@@ -155,23 +214,58 @@ impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A:
             panic!("Light Renderer does only support lit shaders!"); 
         }
 
-        let sample_inv = 1.0 / ((configuration::samples * configuration::samples) as f64);
+        let sample_inv = 1.0 / ((configuration::samples * configuration::samples * self.sampler.time_samples()) as f64);
         for x in 0..configuration::width{
             for y in 0..configuration::height{
                 for s in self.sampler.generate_samples(x, y) {
                     let ray = self.camera.generate_ray(x as f64, y as f64, s);
                     // let ray = self.camera.generate_ray(x as f64, y as f64);
                     let i = self.solver.solve(ray);
+                    if self.debug_overshoot && i.overshoot {
+                        self.film.write_pixel(x, y, [sample_inv, 0.0, sample_inv]);
+                        continue;
+                    }
                     
-                    let mut volumetric = [(i.path_light[0] / f64::from(i.steps) * 0.55) * sample_inv, (i.path_light[1] / f64::from(i.steps) * 0.55) * sample_inv, (i.path_light[2] / f64::from(i.steps) * 0.55) * sample_inv];
+                    let volumetric = [(i.path_light[0] / f64::from(i.steps) * 0.55) * sample_inv, (i.path_light[1] / f64::from(i.steps) * 0.55) * sample_inv, (i.path_light[2] / f64::from(i.steps) * 0.55) * sample_inv];
                     
                     if !i.hit {
-                        //let col = self.shader.miss_color(x, y, i);
-                        self.film.write_pixel(x, y, volumetric);
+                        let glow = match &self.glow {
+                            Some(g) => g.glow(i.min_dist, i.min_dist_step),
+                            None => [0.0, 0.0, 0.0],
+                        };
+                        let background = match &self.environment {
+                            Some(e) => e.sample(ray.0),
+                            None => self.shader.miss_color(x, y, i),
+                        };
+                        self.film.write_pixel(x, y, [volumetric[0] + (glow[0] + background[0]) * sample_inv, volumetric[1] + (glow[1] + background[1]) * sample_inv, volumetric[2] + (glow[2] + background[2]) * sample_inv]);
                     }else{
                         let surface = self.shader.surface_props(x, y, &i);
+                        let view = [-ray.0[0], -ray.0[1], -ray.0[2]];
+
+                        // Ambient and volumetric light are added once, the lights are summed on top
+                        let mut color = [get_f64!(self.ambient[0]) * surface.color[0] * sample_inv + volumetric[0], get_f64!(self.ambient[1]) * surface.color[1] * sample_inv + volumetric[1], get_f64!(self.ambient[2]) * surface.color[2] * sample_inv + volumetric[2]];
+                        let emitted = i.material.emitted();
+                        for c in 0..3 {
+                            color[c] += emitted[c] * sample_inv;
+                        }
+                        if let Some(e) = &self.environment {
+                            // Split into a Lambert part lit by the irradiance and a mirror part lit by the
+                            // pre-filtered reflection, weighted by Fresnel like the BRDF
+                            let n_dot_v = vecmath::vec3_dot(i.shading_normal, view).max(0.0);
+                            let f = brdf::fresnel_schlick(n_dot_v, brdf::base_reflectance(surface.color, i.material.metallic));
+                            let irradiance = e.irradiance(i.shading_normal);
+                            let reflected = e.specular(helpers::reflect(ray.0, i.shading_normal), i.material.roughness);
+                            let metallic = i.material.metallic.clamp(0.0, 1.0);
+                            for c in 0..3 {
+                                let k_d = (1.0 - f[c]) * (1.0 - metallic);
+                                color[c] += (k_d * surface.color[c] * irradiance[c] / PI + f[c] * reflected[c]) * sample_inv;
+                            }
+                        }
                         for l in &self.lights{
                             let light_info = l.illuminate(i.position);
+                            if vecmath::vec3_dot(i.normal, light_info.direction) <= 0.0 {
+                                continue;
+                            }
                             let mut shadow_ray = (light_info.direction, [i.position[0] + i.normal[0] * configuration::small_step, i.position[1] + i.normal[1] * configuration::small_step, i.position[2] + i.normal[2] * configuration::small_step]);
                             let mut i_simple = self.solver.solve_simple(shadow_ray, light_info.distance);
                             if i_simple.steps <= 1 {
@@ -183,19 +277,20 @@ impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A:
                                 }
                             }
                             if !i_simple.hit {
-                                let falloff = i.material.albedo * max_f64(0.0, vecmath::vec3_dot(i.normal, light_info.direction));
-                                let diffuse = [light_info.light_intensity[0] * surface.color[0] * falloff * sample_inv * i.material.diffuse, light_info.light_intensity[1] * surface.color[1] * falloff * sample_inv * i.material.diffuse, light_info.light_intensity[2] * surface.color[2] * falloff * sample_inv * i.material.diffuse];
-
-                                let light_dir_inv = [-light_info.direction[0], -light_info.direction[1], -light_info.direction[2]];
-
-                                let r =  sample_inv * helpers::max_f64(helpers::dot_product(helpers::reflect(light_dir_inv, i.normal), light_info.direction), 0.0).powf(i.material.n_specular);
-                                
-                                let specular = [light_info.light_intensity[0] * r * i.material.specular, light_info.light_intensity[1] * r * i.material.specular, light_info.light_intensity[2] * r * i.material.specular];
-                                self.film.write_pixel(x, y, [diffuse[0] + specular[0] + volumetric[0], diffuse[1] + specular[1] + volumetric[1], diffuse[2] + specular[2] + volumetric[2]]);
-                            }else{
-                                self.film.write_pixel(x, y, [get_f64!(self.ambient[0]) * surface.color[0] * sample_inv + volumetric[0], get_f64!(self.ambient[1]) * surface.color[1] * sample_inv + volumetric[1], get_f64!(self.ambient[2]) * surface.color[2] * sample_inv + volumetric[2]]);
+                                let reflected = brdf::evaluate(&brdf::BrdfInput{
+                                    normal: i.shading_normal,
+                                    view,
+                                    light: light_info.direction,
+                                    color: surface.color,
+                                    metallic: i.material.metallic,
+                                    roughness: i.material.roughness,
+                                });
+                                for c in 0..3 {
+                                    color[c] += light_info.light_intensity[c] * reflected[c] * sample_inv;
+                                }
                             }
                         }
+                        self.film.write_pixel(x, y, color);
                     }
                 }
             }
@@ -214,10 +309,20 @@ impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A:
         self.solver.evaluate(t);
         self.shader.evaluate(t);
     }
+    fn shutter_times(&self) -> Vec<f64>{
+        self.sampler.generate_times()
+    }
+    fn shutter_stratum(&self) -> f64{
+        self.sampler.stratum()
+    }
+    fn evaluate_end(&mut self, t: f64){
+        self.camera.evaluate_end(t);
+    }
 }
 
 impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A: Sampler> LightRenderer<C, F, S, H, A>{
-    pub fn new(camera: C, film: F, solver: S, shader: H, sampler: A, lights: Vec<Box<dyn light::Light>>, ambient: [f64!(); 3]) -> LightRenderer<C, F, S, H, A>{
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(camera: C, film: F, solver: S, shader: H, sampler: A, lights: Vec<Box<dyn light::Light>>, ambient: [f64!(); 3], glow: Option<light::Glow>, environment: Option<environment::EnvironmentMap>) -> LightRenderer<C, F, S, H, A>{
         LightRenderer{
             camera,
             film,
@@ -225,7 +330,14 @@ impl<C: cameras::Camera, F: film::Film, S: solver::Solver, H: shader::Shader, A:
             shader,
             sampler,
             lights,
-            ambient
+            ambient,
+            glow,
+            environment,
+            debug_overshoot: configuration::debug_overshoot
         }
     }
+
+    pub fn set_debug_overshoot(&mut self, debug_overshoot: bool){
+        self.debug_overshoot = debug_overshoot;
+    }
 }
\ No newline at end of file
@@ -2,28 +2,52 @@ use crate::{configuration, CameraSample};
 
 pub trait Sampler{
     fn generate_samples(&self, x: u32, y: u32) -> [CameraSample;(configuration::samples*configuration::samples) as usize];
+    // Starts of the shutter strata in frames, the scene is evaluated and rendered once for each
+    fn generate_times(&self) -> Vec<f64>;
+    // Length of one shutter stratum in frames, the camera moves across it with the time of every sample
+    fn stratum(&self) -> f64;
+    fn time_samples(&self) -> u32;
     fn evaluate(&mut self, t: f64);
 }
 
 pub struct JitterSampler{
     jitter: f64,
-    total_jitter: f64
+    total_jitter: f64,
+    // Motion blur, the shutter interval is given in frames relative to the frame time
+    shutter_open: f64,
+    shutter_close: f64,
+    time_samples: u32
 }
 const SAMPLE_INV: f64 = 1.0 / (configuration::samples as f64);
+const SAMPLE_COUNT_INV: f64 = 1.0 / ((configuration::samples * configuration::samples) as f64);
 
 impl Sampler for JitterSampler{
     fn generate_samples(&self, x: u32, y: u32) -> [CameraSample;(configuration::samples*configuration::samples) as usize]{
-        let mut elements = [((0.0, 0.0),(0.0, 0.0));(configuration::samples*configuration::samples) as usize];
+        let mut elements = [((0.0, 0.0),(0.0, 0.0), 0.0);(configuration::samples*configuration::samples) as usize];
         
         for sx in 0..configuration::samples{
             for sy in 0..configuration::samples{
+                let index = sx * configuration::samples + sy;
                 let film_sample = (sx as f64 * SAMPLE_INV + ((fastrand::f64() - 0.5) * self.total_jitter), sy as f64 * SAMPLE_INV + ((fastrand::f64() - 0.5) * self.total_jitter));
                 let lens_sample = (sx as f64 * SAMPLE_INV + ((fastrand::f64() - 0.5) * self.total_jitter), sy as f64 * SAMPLE_INV + ((fastrand::f64() - 0.5) * self.total_jitter));
-                elements[(sx * configuration::samples + sy) as usize] = (film_sample, lens_sample);
+                // Stratified over the samples of the pixel and random inside every sub stratum, independent of the
+                // film jitter, so the samples cover the whole stratum
+                let time_sample = (index as f64 + fastrand::f64()) * SAMPLE_COUNT_INV;
+                elements[index as usize] = (film_sample, lens_sample, time_sample);
             }
         }
         return elements
     }
+    fn generate_times(&self) -> Vec<f64>{
+        let stratum = self.stratum();
+        (0..self.time_samples).map(|i| self.shutter_open + i as f64 * stratum).collect()
+    }
+    fn stratum(&self) -> f64{
+        (self.shutter_close - self.shutter_open) / self.time_samples as f64
+    }
+    fn time_samples(&self) -> u32{
+        self.time_samples
+    }
     fn evaluate(&mut self, t: f64){
         
     }
@@ -31,6 +55,18 @@ impl Sampler for JitterSampler{
 
 impl JitterSampler{
     pub fn new(jitter: f64) -> JitterSampler{
-        JitterSampler{jitter, total_jitter: jitter * SAMPLE_INV}
+        JitterSampler{
+            jitter,
+            total_jitter: jitter * SAMPLE_INV,
+            shutter_open: configuration::shutter_open,
+            shutter_close: configuration::shutter_close,
+            time_samples: configuration::time_samples
+        }
     }
-}
\ No newline at end of file
+
+    pub fn set_shutter(&mut self, open: f64, close: f64, time_samples: u32){
+        self.shutter_open = open;
+        self.shutter_close = close;
+        self.time_samples = time_samples.max(1);
+    }
+}
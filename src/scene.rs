@@ -1,13 +1,14 @@
 use crate::{
     configuration,
     evaluator::{self, Evaluator},
+    instancer, lsystem, noise, shader, texture,
 };
 use rhai::{Array, Engine, Func};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::{cell::RefCell, rc::Rc};
 
-#[derive(Debug, Deserialize, Copy, Clone)]
+#[derive(Debug, Deserialize, Serialize, Copy, Clone)]
 #[serde()]
 pub struct JsonAnimationKeyframe {
     pub frame: u32,
@@ -18,14 +19,14 @@ pub struct JsonAnimationKeyframe {
     pub inter_y_out: f64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde()]
 pub struct JsonAnimationFloat {
     pub keyframes: Option<Vec<JsonAnimationKeyframe>>,
     pub value: f64,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde()]
 pub struct JsonSceneModifier {
     pub attribute0: JsonAnimationFloat,
@@ -34,9 +35,14 @@ pub struct JsonSceneModifier {
     pub attribute3: JsonAnimationFloat,
     pub attribute4: JsonAnimationFloat,
     pub modifier: i32,
+    #[serde(default)]
+    pub path: Option<String>,
+    // Seed of the noise modifiers, defaults to configuration::noise_seed
+    #[serde(default)]
+    pub seed: Option<u32>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde()]
 pub struct JsonSceneGroupModifier {
     pub prim0: i32,
@@ -44,10 +50,108 @@ pub struct JsonSceneGroupModifier {
     pub prim2: i32,
     pub prim3: i32,
     pub prim_attribute: JsonAnimationFloat,
+    // Step or column count, or the second radius of groove and tongue
+    #[serde(default)]
+    pub prim_attribute2: Option<JsonAnimationFloat>,
     pub modifier: i32,
+    #[serde(default)]
+    pub modifiers: std::vec::Vec<JsonSceneModifier>,
+    #[serde(default)]
+    pub position: Option<[JsonAnimationFloat; 3]>,
+    #[serde(default)]
+    pub rotation: Option<[JsonAnimationFloat; 3]>,
+    #[serde(default)]
+    pub scale: Option<[JsonAnimationFloat; 3]>,
+    #[serde(default)]
+    pub lipschitz: Option<JsonAnimationFloat>,
+    // Members beyond the four prim slots, groups can only contain groups listed before them
+    #[serde(default)]
+    pub objects: std::vec::Vec<i32>,
+    #[serde(default)]
+    pub groups: std::vec::Vec<i32>,
+    // Local bounding sphere of the members as center and radius
+    #[serde(default)]
+    pub bounds: Option<[f64; 4]>,
 }
 
-#[derive(Debug, Deserialize, Clone)]
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde()]
+pub struct JsonSceneInstance {
+    pub position: [f64; 3],
+    #[serde(default)]
+    pub rotation: [f64; 3],
+    #[serde(default)]
+    pub scale: Option<f64>,
+    // albedo, specular, diffuse, n_specular
+    #[serde(default)]
+    pub material: Option<[f64; 4]>,
+    #[serde(default)]
+    pub color: Option<[f64; 3]>,
+    #[serde(default)]
+    pub metallic: Option<f64>,
+    #[serde(default)]
+    pub roughness: Option<f64>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde()]
+pub struct JsonSceneLSystem {
+    pub axiom: String,
+    // Maps a single symbol to its replacement
+    pub rules: std::collections::HashMap<String, String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde()]
+pub struct JsonSceneTexture {
+    // 0 = checker, 1 = Perlin, 2 = simplex, 3 = voronoi, 4 = image from path
+    pub pattern: i32,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub color1: Option<[JsonAnimationFloat; 3]>,
+    #[serde(default)]
+    pub color2: Option<[JsonAnimationFloat; 3]>,
+    pub scale: JsonAnimationFloat,
+    #[serde(default)]
+    pub offset: Option<[JsonAnimationFloat; 3]>,
+    // Seed of the noise patterns, defaults to configuration::noise_seed
+    #[serde(default)]
+    pub seed: Option<u32>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde()]
+pub struct JsonSceneTextures {
+    #[serde(default)]
+    pub albedo: Option<JsonSceneTexture>,
+    #[serde(default)]
+    pub roughness: Option<JsonSceneTexture>,
+    // Multiplies the emission color
+    #[serde(default)]
+    pub emission: Option<JsonSceneTexture>,
+    // Height map that perturbs the shading normal
+    #[serde(default)]
+    pub bump: Option<JsonSceneTexture>,
+    #[serde(default)]
+    pub bump_strength: Option<JsonAnimationFloat>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
+#[serde()]
+pub struct JsonSceneMaterial {
+    pub color: [JsonAnimationFloat; 3],
+    pub metallic: JsonAnimationFloat,
+    pub roughness: JsonAnimationFloat,
+    #[serde(default)]
+    pub emission: Option<[JsonAnimationFloat; 3]>,
+    #[serde(default)]
+    pub emission_strength: Option<JsonAnimationFloat>,
+    #[serde(default)]
+    pub textures: Option<JsonSceneTextures>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone)]
 #[serde()]
 pub struct JsonSceneObject {
     pub position: [JsonAnimationFloat; 3],
@@ -56,6 +160,21 @@ pub struct JsonSceneObject {
     pub prim_type: i32,
     pub values: [JsonAnimationFloat; 10],
     pub modifiers: std::vec::Vec<JsonSceneModifier>,
+    #[serde(default)]
+    pub lipschitz: Option<JsonAnimationFloat>,
+    #[serde(default)]
+    pub path: Option<String>,
+    #[serde(default)]
+    pub points: std::vec::Vec<[JsonAnimationFloat; 3]>,
+    // Index of the object whose transformation this object is relative to
+    #[serde(default)]
+    pub parent: Option<i32>,
+    #[serde(default)]
+    pub instances: std::vec::Vec<JsonSceneInstance>,
+    #[serde(default)]
+    pub lsystem: Option<JsonSceneLSystem>,
+    #[serde(default)]
+    pub material: Option<JsonSceneMaterial>,
 }
 
 #[derive(Debug, Deserialize, Clone)]
@@ -65,12 +184,136 @@ pub struct JsonCamera {
     pub cam_py: [JsonAnimationFloat; 2],
 }
 
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonScenePaletteStop {
+    pub position: f64,
+    pub color: [JsonAnimationFloat; 3],
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonScenePalette {
+    // 0 = x, 1 = y, 2 = z, 3 = r2, 4 = iteration
+    pub channel: i32,
+    pub frequency: JsonAnimationFloat,
+    #[serde(default)]
+    pub offset: Option<JsonAnimationFloat>,
+    #[serde(default)]
+    pub cycle_speed: Option<JsonAnimationFloat>,
+    // a, b, c and d of a cosine palette, used when there are no stops
+    #[serde(default)]
+    pub cosine: Option<[[JsonAnimationFloat; 3]; 4]>,
+    #[serde(default)]
+    pub stops: std::vec::Vec<JsonScenePaletteStop>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneShader {
+    // Script shader, used when there is no palette
+    #[serde(default)]
+    pub path: Option<String>,
+    // Lit shaders only return the surface color, the lights are added by the renderer
+    #[serde(default)]
+    pub lit: Option<bool>,
+    #[serde(default)]
+    pub palette: Option<JsonScenePalette>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneGlow {
+    pub color: [JsonAnimationFloat; 3],
+    pub radius: JsonAnimationFloat,
+    pub falloff: JsonAnimationFloat,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneEnvironment {
+    pub path: String,
+    // Degrees around the y axis
+    #[serde(default)]
+    pub rotation: Option<JsonAnimationFloat>,
+    #[serde(default)]
+    pub intensity: Option<JsonAnimationFloat>,
+}
+
+// Motion blur, open and close are in frames relative to the frame time
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneShutter {
+    pub open: f64,
+    pub close: f64,
+    // Passes over the whole scene, the camera moves with every ray in between
+    #[serde(default)]
+    pub time_samples: Option<u32>,
+}
+
+// Writes the capsules of an L-system object as scene objects instead of rendering the scene
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneExport {
+    pub object: usize,
+    pub path: String,
+}
+
+// Bakes an object into a distance grid file instead of rendering the scene
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneBake {
+    pub object: usize,
+    pub path: String,
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    pub cell_size: f64,
+    #[serde(default)]
+    pub sparse: Option<bool>,
+}
+
+#[derive(Debug, Deserialize, Clone)]
+#[serde()]
+pub struct JsonSceneSky {
+    // Direction the sun light travels, shared with the sun light
+    pub sun: [JsonAnimationFloat; 3],
+    pub turbidity: JsonAnimationFloat,
+    #[serde(default)]
+    pub intensity: Option<JsonAnimationFloat>,
+    #[serde(default)]
+    pub sun_intensity: Option<JsonAnimationFloat>,
+}
+
 #[derive(Debug, Deserialize, Clone)]
 #[serde()]
 pub struct JsonScene {
     pub objects: std::vec::Vec<JsonSceneObject>,
     pub group_modifiers: std::vec::Vec<JsonSceneGroupModifier>,
     pub camera: JsonCamera,
+    #[serde(default)]
+    pub shader: Option<JsonSceneShader>,
+    #[serde(default)]
+    pub glow: Option<JsonSceneGlow>,
+    #[serde(default)]
+    pub environment: Option<JsonSceneEnvironment>,
+    #[serde(default)]
+    pub sky: Option<JsonSceneSky>,
+    #[serde(default)]
+    pub debug_overshoot: Option<bool>,
+    #[serde(default)]
+    pub bake: Option<JsonSceneBake>,
+    #[serde(default)]
+    pub export: Option<JsonSceneExport>,
+    #[serde(default)]
+    pub shutter: Option<JsonSceneShutter>,
+}
+
+// Exported L-system capsules and one group per branch, the last group is the trunk with the transform of the
+// L-system. They index the objects and groups as if both were appended to the scene they were exported from.
+#[derive(Debug, Serialize)]
+pub struct JsonLSystemExport {
+    pub objects: std::vec::Vec<JsonSceneObject>,
+    pub group_modifiers: std::vec::Vec<JsonSceneGroupModifier>,
 }
 
 pub struct SceneModifier {
@@ -80,6 +323,8 @@ pub struct SceneModifier {
     pub attribute3: f64!(),
     pub attribute4: f64!(),
     pub modifier: i32,
+    pub path: Option<String>,
+    pub seed: u32,
 }
 
 pub struct SceneGroupModifier {
@@ -88,7 +333,25 @@ pub struct SceneGroupModifier {
     pub prim2: i32,
     pub prim3: i32,
     pub prim_attribute: f64!(),
+    pub prim_attribute2: f64!(),
     pub modifier: i32,
+    pub modifiers: std::vec::Vec<SceneModifier>,
+    pub position: [f64!(); 3],
+    pub rotation: [f64!(); 3],
+    pub scale: [f64!(); 3],
+    pub lipschitz: Option<f64!()>,
+    pub objects: std::vec::Vec<i32>,
+    pub groups: std::vec::Vec<i32>,
+    pub bounds: Option<([f64; 3], f64)>,
+}
+
+pub struct SceneMaterial {
+    pub color: [f64!(); 3],
+    pub metallic: f64!(),
+    pub roughness: f64!(),
+    pub emission: [f64!(); 3],
+    pub emission_strength: f64!(),
+    pub texture: Option<usize>,
 }
 
 pub struct SceneObject {
@@ -98,16 +361,84 @@ pub struct SceneObject {
     pub prim_type: i32,
     pub values: [f64!(); 10],
     pub modifiers: std::vec::Vec<SceneModifier>,
+    pub lipschitz: Option<f64!()>,
+    pub path: Option<String>,
+    pub points: std::vec::Vec<[f64!(); 3]>,
+    pub parent: Option<i32>,
+    pub instances: std::vec::Vec<instancer::InstanceDesc>,
+    pub lsystem: Option<lsystem::LSystem>,
+    pub material: Option<SceneMaterial>,
 }
 
 pub struct Camera {
     pub cam_pos: [f64!(); 3],
     pub cam_py: [f64!(); 2],
 }
+pub struct ScenePalette {
+    pub palette: shader::Palette,
+    pub channel: shader::OrbitChannel,
+    pub frequency: f64!(),
+    pub offset: f64!(),
+    pub cycle_speed: f64!(),
+}
+
+pub struct SceneShader {
+    pub path: Option<String>,
+    pub lit: bool,
+    pub palette: Option<ScenePalette>,
+}
+
+pub struct SceneEnvironment {
+    pub path: String,
+    pub rotation: f64!(),
+    pub intensity: f64!(),
+}
+
+pub struct SceneShutter {
+    pub open: f64,
+    pub close: f64,
+    pub time_samples: u32,
+}
+
+pub struct SceneExport {
+    pub path: String,
+    pub contents: String,
+}
+
+pub struct SceneBake {
+    pub object: usize,
+    pub path: String,
+    pub min: [f64; 3],
+    pub max: [f64; 3],
+    pub cell_size: f64,
+    pub sparse: bool,
+}
+
+pub struct SceneSky {
+    pub sun: [f64!(); 3],
+    pub turbidity: f64!(),
+    pub intensity: f64!(),
+    pub sun_intensity: f64!(),
+}
+
+pub struct SceneGlow {
+    pub color: [f64!(); 3],
+    pub radius: f64!(),
+    pub falloff: f64!(),
+}
+
 pub struct Scene {
     pub objects: std::vec::Vec<SceneObject>,
     pub group_modifiers: std::vec::Vec<SceneGroupModifier>,
     pub camera: Camera,
+    pub shader: Option<SceneShader>,
+    pub glow: Option<SceneGlow>,
+    pub environment: Option<SceneEnvironment>,
+    pub sky: Option<SceneSky>,
+    pub debug_overshoot: bool,
+    pub bake: Option<SceneBake>,
+    pub export: Option<SceneExport>,
+    pub shutter: SceneShutter,
 }
 
 fn convert_animated_float(f: JsonAnimationFloat) -> f64!() {
@@ -170,6 +501,81 @@ fn convert_offset(f: JsonAnimationFloat, add: f64, mul: f64) -> f64!() {
     }
 }
 
+fn convert_vec3(c: [JsonAnimationFloat; 3]) -> [f64!(); 3] {
+    let [r, g, b] = c;
+    [
+        convert_animated_float(r),
+        convert_animated_float(g),
+        convert_animated_float(b),
+    ]
+}
+
+fn convert_texture(t: JsonSceneTexture) -> texture::Texture {
+    let seed = t.seed.unwrap_or(configuration::noise_seed);
+    let pattern = match t.pattern {
+        1 => texture::Pattern::Noise(noise::Noise::new(seed, noise::NoiseType::Perlin)),
+        2 => texture::Pattern::Noise(noise::Noise::new(seed, noise::NoiseType::Simplex)),
+        3 => texture::Pattern::Voronoi(noise::Noise::new(seed, noise::NoiseType::Voronoi)),
+        4 => texture::load_image(t.path.as_deref().expect("Image textures need a path")),
+        _ => texture::Pattern::Checker,
+    };
+    let color = |c: Option<[JsonAnimationFloat; 3]>, v: f64| match c {
+        Some(c) => convert_vec3(c),
+        None => [f64!(v), f64!(v), f64!(v)],
+    };
+    texture::Texture::new(
+        pattern,
+        color(t.color1, 0.0),
+        color(t.color2, 1.0),
+        convert_animated_float(t.scale),
+        color(t.offset, 0.0),
+    )
+}
+
+fn convert_palette(p: JsonScenePalette) -> ScenePalette {
+    let palette = match p.cosine {
+        Some([a, b, c, d]) if p.stops.is_empty() => shader::Palette::Cosine(
+            convert_vec3(a),
+            convert_vec3(b),
+            convert_vec3(c),
+            convert_vec3(d),
+        ),
+        _ => {
+            let mut stops: Vec<(f64, [f64!(); 3])> = p
+                .stops
+                .into_iter()
+                .map(|s| (s.position, convert_vec3(s.color)))
+                .collect();
+            stops.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
+            shader::Palette::Gradient(stops)
+        }
+    };
+    ScenePalette {
+        palette,
+        channel: shader::OrbitChannel::from_i32(p.channel),
+        frequency: convert_animated_float(p.frequency),
+        offset: p.offset.map(convert_animated_float).unwrap_or(f64!(0.0)),
+        cycle_speed: p.cycle_speed.map(convert_animated_float).unwrap_or(f64!(0.0)),
+    }
+}
+
+fn convert_modifiers(modifiers: std::vec::Vec<JsonSceneModifier>) -> std::vec::Vec<SceneModifier> {
+    let mut mods: Vec<SceneModifier> = Vec::new();
+    for m in modifiers {
+        mods.push(SceneModifier {
+            attribute0: convert_animated_float(m.attribute0),
+            attribute1: convert_animated_float(m.attribute1),
+            attribute2: convert_animated_float(m.attribute2),
+            attribute3: convert_animated_float(m.attribute3),
+            attribute4: convert_animated_float(m.attribute4),
+            modifier: m.modifier,
+            path: m.path,
+            seed: m.seed.unwrap_or(configuration::noise_seed),
+        })
+    }
+    mods
+}
+
 fn convert_script() -> [f64!(); 10] {
     let engine = Engine::new();
     let script = "fn evaluate(t) {
@@ -221,16 +627,138 @@ fn convert_script() -> [f64!(); 10] {
     return values;
 }
 
+fn convert_lsystem(l: JsonSceneLSystem) -> lsystem::LSystem {
+    let mut rules = std::collections::HashMap::new();
+    for (symbol, replacement) in l.rules {
+        match symbol.chars().next() {
+            Some(c) if symbol.chars().count() == 1 => {
+                rules.insert(c, replacement);
+            }
+            _ => panic!("L-system rule {} has to replace a single symbol", symbol),
+        }
+    }
+    lsystem::LSystem::new(l.axiom, rules)
+}
+
+fn static_float(value: f64) -> JsonAnimationFloat {
+    JsonAnimationFloat { keyframes: None, value }
+}
+
+// One capsule object (prim_type 15) per segment with the transformation, modifiers and material of the L-system.
+// Segments of the same branch are smoothly joined by group modifiers of up to four capsules.
+struct LSystemExport<'a> {
+    object: &'a JsonSceneObject,
+    segments: std::vec::Vec<lsystem::Segment>,
+    first_object: i32,
+    first_group: i32,
+    objects: std::vec::Vec<JsonSceneObject>,
+    group_modifiers: std::vec::Vec<JsonSceneGroupModifier>,
+}
+
+impl<'a> LSystemExport<'a> {
+    // Same structure as LSystem::build, returns the object and group indices of the members
+    fn members(&mut self, branch: &lsystem::BranchNode) -> (std::vec::Vec<i32>, std::vec::Vec<i32>) {
+        let mut objects: std::vec::Vec<i32> = Vec::new();
+        let mut groups: std::vec::Vec<i32> = Vec::new();
+        for s in branch.segments.iter().map(|i| &self.segments[*i]) {
+            let mut values: [JsonAnimationFloat; 10] = std::array::from_fn(|_| static_float(0.0));
+            values[0] = static_float(s.radius[0]);
+            values[1] = static_float(s.radius[1]);
+            values[2] = self.object.values[6].clone();
+            values[3] = static_float(s.order);
+            objects.push(self.first_object + self.objects.len() as i32);
+            self.objects.push(JsonSceneObject {
+                prim_type: 15,
+                values,
+                points: vec![s.a.map(static_float), s.b.map(static_float)],
+                position: [0.0, 0.0, 0.0].map(static_float),
+                rotation: [0.0, 0.0, 0.0].map(static_float),
+                scale: [1.0, 1.0, 1.0].map(static_float),
+                modifiers: Vec::new(),
+                lipschitz: None,
+                parent: None,
+                instances: Vec::new(),
+                lsystem: None,
+                path: None,
+                ..self.object.clone()
+            });
+        }
+        for child in &branch.children {
+            let (o, g) = if child.member_count() == 1 {
+                self.members(child)
+            } else {
+                let (o, g) = self.members(child);
+                let (center, radius) = child.bounds();
+                (Vec::new(), vec![self.group(o, g, Some([center[0], center[1], center[2], radius]))])
+            };
+            objects.extend(o);
+            groups.extend(g);
+        }
+        (objects, groups)
+    }
+
+    fn group(&mut self, objects: std::vec::Vec<i32>, groups: std::vec::Vec<i32>, bounds: Option<[f64; 4]>) -> i32 {
+        self.group_modifiers.push(JsonSceneGroupModifier {
+            prim0: -1,
+            prim1: -1,
+            prim2: -1,
+            prim3: -1,
+            prim_attribute: self.object.values[5].clone(),
+            prim_attribute2: None,
+            // Smooth union
+            modifier: 4,
+            modifiers: Vec::new(),
+            position: None,
+            rotation: None,
+            scale: None,
+            lipschitz: None,
+            objects,
+            groups,
+            bounds,
+        });
+        self.first_group + self.group_modifiers.len() as i32 - 1
+    }
+}
+
+fn export_lsystem(json: &JsonScene, index: usize) -> JsonLSystemExport {
+    let o = json.objects.get(index).unwrap_or_else(|| panic!("Export object {} is not a valid object", index));
+    let l = o.lsystem.clone().unwrap_or_else(|| panic!("Export object {} is not an L-system", index));
+    let (segments, root) = convert_lsystem(l).tree(
+        o.values[1].value.max(0.0) as u32,
+        o.values[0].value,
+        o.values[2].value,
+        o.values[3].value,
+        o.values[4].value,
+    );
+    let root = root.expect("L-system does not draw any segments");
+
+    let mut export = LSystemExport {
+        object: o,
+        segments,
+        first_object: json.objects.len() as i32,
+        first_group: json.group_modifiers.len() as i32,
+        objects: Vec::new(),
+        group_modifiers: Vec::new(),
+    };
+    let (objects, groups) = export.members(&root);
+    export.group(objects, groups, None);
+    // The trunk carries the transform and modifiers of the L-system object like its rendered group
+    if let Some(trunk) = export.group_modifiers.last_mut() {
+        trunk.modifiers = o.modifiers.clone();
+        trunk.position = Some(o.position.clone());
+        trunk.rotation = Some(o.rotation.clone());
+        trunk.scale = Some(o.scale.clone());
+        trunk.lipschitz = o.lipschitz.clone();
+    }
+    JsonLSystemExport { objects: export.objects, group_modifiers: export.group_modifiers }
+}
+
 pub fn load_scene(path: &str) -> Scene {
     let contents = fs::read_to_string(path).expect("Something went wrong reading the file");
     let json: JsonScene = serde_json::from_str(&contents).expect("JSON was not well-formatted");
 
     let camera: Camera = Camera {
-        cam_pos: [
-            convert_animated_float(json.camera.cam_pos[0].to_owned()),
-            convert_animated_float(json.camera.cam_pos[1].to_owned()),
-            convert_animated_float(json.camera.cam_pos[2].to_owned()),
-        ],
+        cam_pos: convert_vec3(json.camera.cam_pos.to_owned()),
         cam_py: [
             convert_offset(json.camera.cam_py[0].to_owned(), 0.0, -1.0),
             convert_offset(json.camera.cam_py[1].to_owned(), 90.0, -1.0),
@@ -238,6 +766,12 @@ pub fn load_scene(path: &str) -> Scene {
     };
     //[-scene.camera.cam_py[0].value, -(scene.camera.cam_py[1].value+90.0), 0.0])
 
+    let export = json.export.as_ref().map(|e| SceneExport {
+        path: e.path.clone(),
+        contents: serde_json::to_string_pretty(&export_lsystem(&json, e.object))
+            .expect("Something went wrong serializing the L-system"),
+    });
+
     let mut group_modifiers: std::vec::Vec<SceneGroupModifier> = Vec::new();
     for g in json.group_modifiers {
         group_modifiers.push(SceneGroupModifier {
@@ -246,39 +780,26 @@ pub fn load_scene(path: &str) -> Scene {
             prim2: g.prim2,
             prim3: g.prim3,
             prim_attribute: convert_animated_float(g.prim_attribute),
+            prim_attribute2: g.prim_attribute2.map(convert_animated_float).unwrap_or(f64!(0.0)),
             modifier: g.modifier,
+            modifiers: convert_modifiers(g.modifiers),
+            position: g.position.map(convert_vec3).unwrap_or(f64v!([0.0, 0.0, 0.0])),
+            rotation: g.rotation.map(convert_vec3).unwrap_or(f64v!([0.0, 0.0, 0.0])),
+            scale: g.scale.map(convert_vec3).unwrap_or(f64v!([1.0, 1.0, 1.0])),
+            lipschitz: g.lipschitz.map(convert_animated_float),
+            objects: g.objects,
+            groups: g.groups,
+            bounds: g.bounds.map(|b| ([b[0], b[1], b[2]], b[3])),
         });
     }
 
     let mut objects: std::vec::Vec<SceneObject> = Vec::new();
     for o in json.objects {
-        let mut mods: Vec<SceneModifier> = Vec::new();
-        for m in o.modifiers {
-            mods.push(SceneModifier {
-                attribute0: convert_animated_float(m.attribute0),
-                attribute1: convert_animated_float(m.attribute1),
-                attribute2: convert_animated_float(m.attribute2),
-                attribute3: convert_animated_float(m.attribute3),
-                attribute4: convert_animated_float(m.attribute4),
-                modifier: m.modifier,
-            })
-        }
+        let mods = convert_modifiers(o.modifiers);
         objects.push(SceneObject {
-            position: [
-                convert_animated_float(o.position[0].to_owned()),
-                convert_animated_float(o.position[1].to_owned()),
-                convert_animated_float(o.position[2].to_owned()),
-            ],
-            rotation: [
-                convert_animated_float(o.rotation[0].to_owned()),
-                convert_animated_float(o.rotation[1].to_owned()),
-                convert_animated_float(o.rotation[2].to_owned()),
-            ],
-            scale: [
-                convert_animated_float(o.scale[0].to_owned()),
-                convert_animated_float(o.scale[1].to_owned()),
-                convert_animated_float(o.scale[2].to_owned()),
-            ],
+            position: convert_vec3(o.position),
+            rotation: convert_vec3(o.rotation),
+            scale: convert_vec3(o.scale),
             prim_type: o.prim_type,
             values: [
                 convert_animated_float(o.values[0].to_owned()),
@@ -294,6 +815,47 @@ pub fn load_scene(path: &str) -> Scene {
             ],
             // values: convert_script(),
             modifiers: mods,
+            lipschitz: o.lipschitz.map(convert_animated_float),
+            path: o.path,
+            points: o.points.into_iter().map(convert_vec3).collect(),
+            parent: o.parent,
+            instances: o
+                .instances
+                .iter()
+                .map(|i| instancer::InstanceDesc {
+                    pos: i.position,
+                    rot: i.rotation,
+                    scale: i.scale.unwrap_or(1.0),
+                    material: instancer::InstanceMaterial {
+                        shading: i.material,
+                        color: i.color,
+                        metallic: i.metallic,
+                        roughness: i.roughness,
+                    },
+                })
+                .collect(),
+            lsystem: o.lsystem.map(convert_lsystem),
+            material: o.material.map(|m| {
+                SceneMaterial {
+                    color: convert_vec3(m.color),
+                    metallic: convert_animated_float(m.metallic),
+                    roughness: convert_animated_float(m.roughness),
+                    emission: m.emission.map(convert_vec3).unwrap_or(f64v!([0.0, 0.0, 0.0])),
+                    emission_strength: m
+                        .emission_strength
+                        .map(convert_animated_float)
+                        .unwrap_or(f64!(0.0)),
+                    texture: m.textures.map(|t| {
+                        texture::register(texture::TextureSet {
+                            albedo: t.albedo.map(convert_texture),
+                            roughness: t.roughness.map(convert_texture),
+                            emission: t.emission.map(convert_texture),
+                            bump: t.bump.map(convert_texture),
+                            bump_strength: t.bump_strength.map(convert_animated_float).unwrap_or(f64!(1.0)),
+                        })
+                    }),
+                }
+            }),
         })
     }
 
@@ -301,5 +863,48 @@ pub fn load_scene(path: &str) -> Scene {
         objects: objects,
         group_modifiers: group_modifiers,
         camera: camera,
+        shader: json.shader.map(|s| SceneShader {
+            path: s.path,
+            lit: s.lit.unwrap_or(true),
+            palette: s.palette.map(convert_palette),
+        }),
+        glow: json.glow.map(|glow| SceneGlow {
+            color: convert_vec3(glow.color),
+            radius: convert_animated_float(glow.radius),
+            falloff: convert_animated_float(glow.falloff),
+        }),
+        environment: json.environment.map(|e| SceneEnvironment {
+            path: e.path,
+            rotation: e.rotation.map(convert_animated_float).unwrap_or(f64!(0.0)),
+            intensity: e.intensity.map(convert_animated_float).unwrap_or(f64!(1.0)),
+        }),
+        sky: json.sky.map(|sky| SceneSky {
+            sun: convert_vec3(sky.sun),
+            turbidity: convert_animated_float(sky.turbidity),
+            intensity: sky.intensity.map(convert_animated_float).unwrap_or(f64!(1.0)),
+            sun_intensity: sky.sun_intensity.map(convert_animated_float).unwrap_or(f64!(1.5)),
+        }),
+        debug_overshoot: json.debug_overshoot.unwrap_or(configuration::debug_overshoot),
+        bake: json.bake.map(|b| SceneBake {
+            object: b.object,
+            path: b.path,
+            min: b.min,
+            max: b.max,
+            cell_size: b.cell_size,
+            sparse: b.sparse.unwrap_or(true),
+        }),
+        export,
+        shutter: match json.shutter {
+            Some(shutter) => SceneShutter {
+                open: shutter.open,
+                close: shutter.close,
+                time_samples: shutter.time_samples.unwrap_or(configuration::time_samples),
+            },
+            None => SceneShutter {
+                open: configuration::shutter_open,
+                close: configuration::shutter_close,
+                time_samples: configuration::time_samples,
+            },
+        },
     };
 }
@@ -0,0 +1,68 @@
+// Rhai scripts loaded from scene files. Scripts can read the animated parameters of their object with param(i).
+use rhai::{Array, Dynamic, Engine, Scope, AST};
+use std::path::PathBuf;
+use std::{cell::RefCell, rc::Rc};
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    pub fn load(path: &str, params: Vec<f64!()>) -> Script {
+        let mut engine = Engine::new();
+        // The default limits of debug builds are too low for typical distance functions
+        engine.set_max_expr_depths(64, 64);
+        engine.register_fn("param", move |i: i64| match params.get(i as usize) {
+            Some(v) => get_ref_f64!(v),
+            None => 0.0,
+        });
+
+        let ast = match engine.compile_file(PathBuf::from(path)) {
+            Ok(ast) => ast,
+            Err(error) => panic!("Problem loading script {}: {:?}", path, error),
+        };
+
+        Script { engine, ast }
+    }
+
+    pub fn has_fn(&self, name: &str, arity: usize) -> bool {
+        self.ast
+            .iter_functions()
+            .any(|f| f.name == name && f.params.len() == arity)
+    }
+
+    // Only runs the function body, top level statements of the script are skipped
+    pub fn call(&self, name: &str, args: Vec<Dynamic>) -> Dynamic {
+        match self
+            .engine
+            .call_fn_raw(&mut Scope::new(), &self.ast, false, false, name, None, args)
+        {
+            Ok(res) => res,
+            Err(error) => panic!("Problem running script function {}: {:?}", name, error),
+        }
+    }
+}
+
+pub fn vec3_to_dynamic(v: [f64; 3]) -> Dynamic {
+    let a: Array = vec![Dynamic::from_float(v[0]), Dynamic::from_float(v[1]), Dynamic::from_float(v[2])];
+    Dynamic::from_array(a)
+}
+
+// Integers are accepted as well, scripts often write 0 instead of 0.0
+pub fn dynamic_to_f64(v: &Dynamic) -> f64 {
+    if let Ok(f) = v.as_float() {
+        return f;
+    }
+    if let Ok(i) = v.as_int() {
+        return i as f64;
+    }
+    panic!("Script returned {} where a number was expected", v.type_name());
+}
+
+pub fn dynamic_to_vec(v: Dynamic) -> Vec<f64> {
+    if v.is::<Array>() {
+        return v.cast::<Array>().iter().map(dynamic_to_f64).collect();
+    }
+    vec![dynamic_to_f64(&v)]
+}
@@ -0,0 +1,214 @@
+// 2D signed distance functions, used as profiles by the extrusion and revolution primitives.
+// Translated from the Inigo Quilez 2D distance functions.
+use std::{cell::RefCell, rc::Rc};
+
+pub trait Shape2D {
+    fn distance(&self, p: [f64; 2]) -> f64;
+}
+
+#[inline(always)]
+fn dot(a: [f64; 2], b: [f64; 2]) -> f64 {
+    a[0] * b[0] + a[1] * b[1]
+}
+
+#[inline(always)]
+fn length(a: [f64; 2]) -> f64 {
+    dot(a, a).sqrt()
+}
+
+// ------------------------------------------
+//                  CIRCLE
+// ------------------------------------------
+
+pub struct Circle {
+    radius: f64!(),
+}
+
+impl Shape2D for Circle {
+    fn distance(&self, p: [f64; 2]) -> f64 {
+        length(p) - get_f64!(self.radius)
+    }
+}
+
+impl Circle {
+    pub fn new(radius: f64!()) -> Circle {
+        Circle { radius }
+    }
+}
+
+// ------------------------------------------
+//                 RECTANGLE
+// ------------------------------------------
+
+pub struct Rectangle {
+    half_size: [f64!(); 2],
+}
+
+impl Shape2D for Rectangle {
+    fn distance(&self, p: [f64; 2]) -> f64 {
+        let d = [
+            p[0].abs() - get_f64!(self.half_size[0]),
+            p[1].abs() - get_f64!(self.half_size[1]),
+        ];
+        length([d[0].max(0.0), d[1].max(0.0)]) + d[0].max(d[1]).min(0.0)
+    }
+}
+
+impl Rectangle {
+    pub fn new(half_size: [f64!(); 2]) -> Rectangle {
+        Rectangle { half_size }
+    }
+}
+
+// ------------------------------------------
+//                  POLYGON
+// ------------------------------------------
+
+pub struct Polygon {
+    points: Vec<[f64!(); 2]>,
+}
+
+impl Shape2D for Polygon {
+    fn distance(&self, p: [f64; 2]) -> f64 {
+        let v: Vec<[f64; 2]> = self
+            .points
+            .iter()
+            .map(|v| [get_f64!(v[0]), get_f64!(v[1])])
+            .collect();
+        if v.len() < 3 {
+            return f64::MAX;
+        }
+
+        let first = [p[0] - v[0][0], p[1] - v[0][1]];
+        let mut d = dot(first, first);
+        let mut s = 1.0;
+        let mut j = v.len() - 1;
+        for i in 0..v.len() {
+            let e = [v[j][0] - v[i][0], v[j][1] - v[i][1]];
+            let w = [p[0] - v[i][0], p[1] - v[i][1]];
+            let h = (dot(w, e) / dot(e, e)).clamp(0.0, 1.0);
+            let b = [w[0] - e[0] * h, w[1] - e[1] * h];
+            d = d.min(dot(b, b));
+
+            // Winding number test for the sign
+            let c = [p[1] >= v[i][1], p[1] < v[j][1], e[0] * w[1] > e[1] * w[0]];
+            if (c[0] && c[1] && c[2]) || (!c[0] && !c[1] && !c[2]) {
+                s = -s;
+            }
+            j = i;
+        }
+        s * d.sqrt()
+    }
+}
+
+impl Polygon {
+    pub fn new(points: Vec<[f64!(); 2]>) -> Polygon {
+        Polygon { points }
+    }
+}
+
+// ------------------------------------------
+//                   STAR
+// ------------------------------------------
+
+pub struct Star {
+    radius: f64!(),
+    points: f64!(),
+    // Between 2 and points, smaller values give sharper tips
+    sharpness: f64!(),
+}
+
+impl Shape2D for Star {
+    fn distance(&self, p: [f64; 2]) -> f64 {
+        let r = get_f64!(self.radius);
+        let n = get_f64!(self.points).max(2.0);
+        let m = get_f64!(self.sharpness).clamp(2.0, n);
+
+        let an = std::f64::consts::PI / n;
+        let en = std::f64::consts::PI / m;
+        let acs = [an.cos(), an.sin()];
+        let ecs = [en.cos(), en.sin()];
+
+        let bn = p[0].atan2(p[1]).rem_euclid(2.0 * an) - an;
+        let l = length(p);
+        let mut q = [l * bn.cos() - r * acs[0], l * bn.sin().abs() - r * acs[1]];
+        let h = (-dot(q, ecs)).clamp(0.0, r * acs[1] / ecs[1]);
+        q = [q[0] + ecs[0] * h, q[1] + ecs[1] * h];
+        length(q) * q[0].signum()
+    }
+}
+
+impl Star {
+    pub fn new(radius: f64!(), points: f64!(), sharpness: f64!()) -> Star {
+        Star {
+            radius,
+            points,
+            sharpness,
+        }
+    }
+}
+
+// ------------------------------------------
+//                    ARC
+// ------------------------------------------
+
+pub struct Arc {
+    // Half of the opening angle in degrees, symmetric around the y axis
+    aperture: f64!(),
+    radius: f64!(),
+    thickness: f64!(),
+}
+
+impl Shape2D for Arc {
+    fn distance(&self, p: [f64; 2]) -> f64 {
+        let aperture = get_f64!(self.aperture).to_radians();
+        let sc = [aperture.sin(), aperture.cos()];
+        let ra = get_f64!(self.radius);
+        let q = [p[0].abs(), p[1]];
+
+        let d = if sc[1] * q[0] > sc[0] * q[1] {
+            length([q[0] - sc[0] * ra, q[1] - sc[1] * ra])
+        } else {
+            (length(q) - ra).abs()
+        };
+        d - get_f64!(self.thickness)
+    }
+}
+
+impl Arc {
+    pub fn new(aperture: f64!(), radius: f64!(), thickness: f64!()) -> Arc {
+        Arc {
+            aperture,
+            radius,
+            thickness,
+        }
+    }
+}
+
+// ------------------------------------------
+//                  SEGMENT
+// ------------------------------------------
+
+pub struct Segment {
+    a: [f64!(); 2],
+    b: [f64!(); 2],
+    thickness: f64!(),
+}
+
+impl Shape2D for Segment {
+    fn distance(&self, p: [f64; 2]) -> f64 {
+        let a = [get_f64!(self.a[0]), get_f64!(self.a[1])];
+        let b = [get_f64!(self.b[0]), get_f64!(self.b[1])];
+        let pa = [p[0] - a[0], p[1] - a[1]];
+        let ba = [b[0] - a[0], b[1] - a[1]];
+        let len = dot(ba, ba);
+        let h = if len > 0.0 { (dot(pa, ba) / len).clamp(0.0, 1.0) } else { 0.0 };
+        length([pa[0] - ba[0] * h, pa[1] - ba[1] * h]) - get_f64!(self.thickness)
+    }
+}
+
+impl Segment {
+    pub fn new(a: [f64!(); 2], b: [f64!(); 2], thickness: f64!()) -> Segment {
+        Segment { a, b, thickness }
+    }
+}
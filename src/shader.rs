@@ -1,6 +1,8 @@
 use crate::configuration;
+use crate::script;
 use crate::solver;
-use std::{rc::Rc, cell::RefCell};
+use rhai::Dynamic;
+use std::{rc::Rc, cell::RefCell, f64::consts::PI};
 use crate::helpers;
 
 pub trait Shader {
@@ -9,8 +11,24 @@ pub trait Shader {
     fn is_lit(&self) -> bool;
     fn evaluate(&mut self, t: f64);
 }
+// Lets the shader be picked at runtime, e.g. from the scene file
+impl Shader for Box<dyn Shader> {
+    fn surface_props(&self, x: u32, y: u32, i: &solver::IntersectionInfo) -> solver::SufraceProperties {
+        self.as_ref().surface_props(x, y, i)
+    }
+    fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3] {
+        self.as_ref().miss_color(x, y, i)
+    }
+    fn is_lit(&self) -> bool {
+        self.as_ref().is_lit()
+    }
+    fn evaluate(&mut self, t: f64) {
+        self.as_mut().evaluate(t)
+    }
+}
 pub trait BackgroundShader {
-    fn shade(&self, x: u32, y: u32) -> [f64;3];
+    // direction is the normalized direction of the camera ray
+    fn shade(&self, x: u32, y: u32, direction: [f64;3]) -> [f64;3];
     fn evaluate(&mut self, t: f64);
 }
 
@@ -35,7 +53,7 @@ impl Shader for ColorShader{
         return solver::SufraceProperties{color: self.color.clone()};
     }
     fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3]{
-        return self.background_shader.shade(x, y);
+        self.background_shader.shade(x, y, i.direction)
     }
     fn evaluate(&mut self, t: f64){
         
@@ -64,10 +82,10 @@ impl NormalShader{
 }
 impl Shader for NormalShader{
     fn surface_props(&self, x: u32, y: u32, i: &solver::IntersectionInfo) -> solver::SufraceProperties{
-        return solver::SufraceProperties{color: [i.normal[0] * 0.5 + 0.5, i.normal[1] * 0.5 + 0.5, i.normal[2] * 0.5 + 0.5]}
+        solver::SufraceProperties{color: [i.shading_normal[0] * 0.5 + 0.5, i.shading_normal[1] * 0.5 + 0.5, i.shading_normal[2] * 0.5 + 0.5]}
     }
     fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3]{
-        return self.background_shader.shade(x, y);
+        self.background_shader.shade(x, y, i.direction)
     }
     fn evaluate(&mut self, t: f64){
         
@@ -92,32 +110,45 @@ pub struct FractalShader{
     col1: [f64!();3],
     col2: [f64!();3],
     darkness: f64!(),
+    // Rotation in degrees like the camera, the light shines along the rotated -z axis
     light_dir: [f64!();3],
+    // Updated in evaluate, a darkness of 0 leaves the color unchanged
+    darkness_inv: f64,
+    to_light: [f64;3],
     background_shader: Box<dyn BackgroundShader>
 }
 impl FractalShader{
     pub fn new(col1: [f64!();3], col2: [f64!();3], darkness: f64!(), light_dir: [f64!();3], background_shader: Box<dyn BackgroundShader>) -> FractalShader{
-        FractalShader{col1,col2,darkness,light_dir,background_shader}
+        let mut shader = FractalShader{col1,col2,darkness,light_dir,darkness_inv: 1.0,to_light: [0.0, 0.0, 1.0],background_shader};
+        shader.update();
+        shader
+    }
+
+    fn update(&mut self){
+        let darkness = get_f64!(self.darkness);
+        self.darkness_inv = if darkness != 0.0 { 1.0 / darkness } else { 1.0 };
+        self.to_light = helpers::mat_dir_mul3(helpers::mat_rotation3(get_f64v!(self.light_dir)), [0.0, 0.0, 1.0]);
     }
 }
 impl Shader for FractalShader{
     fn surface_props(&self, x: u32, y: u32, i: &solver::IntersectionInfo) -> solver::SufraceProperties{
         let col1 = get_f64v!(self.col1);
         let col2 = get_f64v!(self.col2);
-        let light_dir = get_f64v!(self.light_dir);
 
-        let a = vecmath::vec3_dot([i.normal[0] * 0.5 + 0.5, i.normal[1] * 0.5 + 0.5, i.normal[2] * 0.5 + 0.5], light_dir).clamp(0.0, 1.0);
-        let b = (i.fractal_data[0] / 16.0).clamp(0.0, 1.0);
+        let a = vecmath::vec3_dot(i.shading_normal, self.to_light).clamp(0.0, 1.0);
+        // Smooth iteration count, the Mandelbulb iterates at most 15 times
+        let b = (i.fractal_data[4] / 16.0).clamp(0.0, 1.0);
         let color_mix = helpers::vec_clamp([a * col1[0] + b * col2[0], a * col1[1] + b * col2[1], a * col1[2] + b * col2[2]], 0.0, 1.0);
 
-        let rim = (i.steps as f64) / get_f64!(self.darkness);
+        let rim = (i.steps as f64) * self.darkness_inv;
         return solver::SufraceProperties{color: [color_mix[0] * rim, color_mix[1] * rim, color_mix[2] * rim]};
     }
     fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3]{
-        return self.background_shader.shade(x, y);
+        self.background_shader.shade(x, y, i.direction)
     }
     fn evaluate(&mut self, t: f64){
-        
+        self.update();
+        self.background_shader.evaluate(t);
     }
     fn is_lit(&self) -> bool{
         return false;
@@ -125,6 +156,113 @@ impl Shader for FractalShader{
 }
 
 
+// ------------------------------------------
+//                 PALETTE
+// ------------------------------------------
+
+// Channel of the fractal data that drives the palette
+#[derive(Clone, Copy)]
+pub enum OrbitChannel {
+    X = 0,
+    Y = 1,
+    Z = 2,
+    R2 = 3,
+    Iteration = 4,
+}
+
+impl OrbitChannel {
+    pub fn from_i32(value: i32) -> OrbitChannel {
+        match value {
+            1 => OrbitChannel::Y,
+            2 => OrbitChannel::Z,
+            3 => OrbitChannel::R2,
+            4 => OrbitChannel::Iteration,
+            _ => OrbitChannel::X,
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum Palette {
+    // a + b * cos(2 pi (c * t + d)), see https://iquilezles.org/articles/palettes/
+    Cosine([f64!();3], [f64!();3], [f64!();3], [f64!();3]),
+    // Stops of (position, color) sorted by position in [0, 1], the gradient repeats outside
+    Gradient(Vec<(f64, [f64!();3])>),
+}
+
+impl Palette {
+    pub fn color(&self, t: f64) -> [f64;3] {
+        match self {
+            Palette::Cosine(a, b, c, d) => {
+                let mut res = [0.0; 3];
+                for k in 0..3 {
+                    res[k] = get_f64!(a[k]) + get_f64!(b[k]) * (2.0 * PI * (get_f64!(c[k]) * t + get_f64!(d[k]))).cos();
+                }
+                res
+            }
+            Palette::Gradient(stops) => {
+                if stops.is_empty() {
+                    return [0.0, 0.0, 0.0];
+                }
+                let t = t - t.floor();
+                let first = &stops[0];
+                let last = &stops[stops.len() - 1];
+                if t <= first.0 {
+                    return get_f64v!(first.1);
+                }
+                for w in stops.windows(2) {
+                    if t <= w[1].0 {
+                        let f = (t - w[0].0) / (w[1].0 - w[0].0).max(1e-9);
+                        return helpers::vec_interpolate(get_f64v!(w[0].1), get_f64v!(w[1].1), f);
+                    }
+                }
+                get_f64v!(last.1)
+            }
+        }
+    }
+}
+
+// Colors fractals by one orbit trap channel through a palette. Lit palettes only return the base color, the
+// lights are added by the renderer.
+pub struct PaletteShader{
+    palette: Palette,
+    channel: OrbitChannel,
+    frequency: f64!(),
+    offset: f64!(),
+    // Palette cycles per second
+    cycle_speed: f64!(),
+    lit: bool,
+    time: f64,
+    background_shader: Box<dyn BackgroundShader>
+}
+impl PaletteShader{
+    pub fn new(palette: Palette, channel: OrbitChannel, frequency: f64!(), offset: f64!(), cycle_speed: f64!(), lit: bool, background_shader: Box<dyn BackgroundShader>) -> PaletteShader{
+        PaletteShader{palette, channel, frequency, offset, cycle_speed, lit, time: 0.0, background_shader}
+    }
+}
+impl Shader for PaletteShader{
+    fn surface_props(&self, x: u32, y: u32, i: &solver::IntersectionInfo) -> solver::SufraceProperties{
+        let value = i.fractal_data[self.channel as usize];
+        let t = value * get_f64!(self.frequency) + get_f64!(self.offset) + get_f64!(self.cycle_speed) * self.time;
+        let color = helpers::vec_f_max(self.palette.color(t), 0.0);
+        if self.lit {
+            let albedo = i.material.albedo;
+            return solver::SufraceProperties{color: [color[0] * albedo, color[1] * albedo, color[2] * albedo]};
+        }
+        solver::SufraceProperties{color}
+    }
+    fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3]{
+        self.background_shader.shade(x, y, i.direction)
+    }
+    fn evaluate(&mut self, t: f64){
+        self.time = t;
+    }
+    fn is_lit(&self) -> bool{
+        self.lit
+    }
+}
+
+
 // ------------------------------------------
 // ------------------------------------------
 // ------------------------------------------
@@ -137,23 +275,25 @@ impl Shader for FractalShader{
 
 
 // ------------------------------------------
-//                  PHONG
+//                   PBR
 // ------------------------------------------
 
-pub struct PhongShader{
+// Base color of the material, the lighting is done by the renderer with the metallic/roughness BRDF
+pub struct PbrShader{
     background_shader: Box<dyn BackgroundShader>
 }
-impl PhongShader{
-    pub fn new(background_shader: Box<dyn BackgroundShader>) -> PhongShader{
-        PhongShader{background_shader}
+impl PbrShader{
+    pub fn new(background_shader: Box<dyn BackgroundShader>) -> PbrShader{
+        PbrShader{background_shader}
     }
 }
-impl Shader for PhongShader{
+impl Shader for PbrShader{
     fn surface_props(&self, x: u32, y: u32, i: &solver::IntersectionInfo) -> solver::SufraceProperties{
-        return solver::SufraceProperties{color: [1.0, 1.0, 1.0]};
+        let albedo = i.material.albedo;
+        solver::SufraceProperties{color: [i.material.color[0] * albedo, i.material.color[1] * albedo, i.material.color[2] * albedo]}
     }
     fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3]{
-        return self.background_shader.shade(x, y);
+        self.background_shader.shade(x, y, i.direction)
     }
     fn evaluate(&mut self, t: f64){
         
@@ -163,6 +303,69 @@ impl Shader for PhongShader{
     }
 }
 
+// ------------------------------------------
+//                  SCRIPT
+// ------------------------------------------
+
+// The script defines fn shade(hit) returning [r, g, b]. hit has the fields of the IntersectionInfo, plus
+// the material as a map and the current time.
+pub struct ScriptShader{
+    script: script::Script,
+    lit: bool,
+    time: f64,
+    background_shader: Box<dyn BackgroundShader>
+}
+impl ScriptShader{
+    pub fn new(path: &str, lit: bool, background_shader: Box<dyn BackgroundShader>) -> ScriptShader{
+        let script = script::Script::load(path, Vec::new());
+        if !script.has_fn("shade", 1) {
+            panic!("Script {} has no fn shade(hit)", path);
+        }
+        ScriptShader{script, lit, time: 0.0, background_shader}
+    }
+}
+impl Shader for ScriptShader{
+    fn surface_props(&self, x: u32, y: u32, i: &solver::IntersectionInfo) -> solver::SufraceProperties{
+        let mut material = rhai::Map::new();
+        material.insert("albedo".into(), Dynamic::from_float(i.material.albedo));
+        material.insert("specular".into(), Dynamic::from_float(i.material.specular));
+        material.insert("diffuse".into(), Dynamic::from_float(i.material.diffuse));
+        material.insert("n_specular".into(), Dynamic::from_float(i.material.n_specular));
+        material.insert("color".into(), script::vec3_to_dynamic(i.material.color));
+        material.insert("metallic".into(), Dynamic::from_float(i.material.metallic));
+        material.insert("roughness".into(), Dynamic::from_float(i.material.roughness));
+        material.insert("emission".into(), script::vec3_to_dynamic(i.material.emission));
+        material.insert("emission_strength".into(), Dynamic::from_float(i.material.emission_strength));
+
+        let mut hit = rhai::Map::new();
+        hit.insert("normal".into(), script::vec3_to_dynamic(i.shading_normal));
+        hit.insert("geometric_normal".into(), script::vec3_to_dynamic(i.normal));
+        hit.insert("position".into(), script::vec3_to_dynamic(i.position));
+        hit.insert("steps".into(), Dynamic::from_int(i.steps as i64));
+        hit.insert("dist".into(), Dynamic::from_float(i.dist));
+        hit.insert("min_dist".into(), Dynamic::from_float(i.min_dist));
+        hit.insert("min_dist_step".into(), Dynamic::from_int(i.min_dist_step as i64));
+        hit.insert("fractal_data".into(), Dynamic::from_array(i.fractal_data.iter().map(|v| Dynamic::from_float(*v)).collect()));
+        hit.insert("material".into(), Dynamic::from_map(material));
+        hit.insert("time".into(), Dynamic::from_float(self.time));
+
+        let res = script::dynamic_to_vec(self.script.call("shade", vec![Dynamic::from_map(hit)]));
+        if res.len() < 3 {
+            panic!("Script fn shade(hit) has to return an array of 3 numbers");
+        }
+        solver::SufraceProperties{color: [res[0], res[1], res[2]]}
+    }
+    fn miss_color(&self, x: u32, y: u32, i: solver::IntersectionInfo) -> [f64;3]{
+        self.background_shader.shade(x, y, i.direction)
+    }
+    fn evaluate(&mut self, t: f64){
+        self.time = t;
+    }
+    fn is_lit(&self) -> bool{
+        self.lit
+    }
+}
+
 // ------------------------------------------
 // ------------------------------------------
 // ------------------------------------------
@@ -186,7 +389,7 @@ impl BackgroundColorShader{
     }
 }
 impl BackgroundShader for BackgroundColorShader{
-    fn shade(&self, x: u32, y: u32) -> [f64;3]{
+    fn shade(&self, x: u32, y: u32, direction: [f64;3]) -> [f64;3]{
         self.color.clone()
     }
     fn evaluate(&mut self, t: f64){
@@ -208,7 +411,7 @@ impl BackgroundLinearXGradient{
     }
 }
 impl BackgroundShader for BackgroundLinearXGradient{
-    fn shade(&self, x: u32, y: u32) -> [f64;3]{
+    fn shade(&self, x: u32, y: u32, direction: [f64;3]) -> [f64;3]{
         return helpers::vec_interpolate(self.color1, self.color2, x as f64 / configuration::width_f);
     }
     fn evaluate(&mut self, t: f64){
@@ -232,7 +435,7 @@ impl BackgroundLinearYGradient{
     }
 }
 impl BackgroundShader for BackgroundLinearYGradient{
-    fn shade(&self, x: u32, y: u32) -> [f64;3]{
+    fn shade(&self, x: u32, y: u32, direction: [f64;3]) -> [f64;3]{
         return helpers::vec_interpolate(self.color1, self.color2, y as f64 / configuration::height_f);
     }
     fn evaluate(&mut self, t: f64){
@@ -0,0 +1,172 @@
+// Physical daylight sky after Preetham, Shirley and Smits, "A Practical Analytic Model for Daylight". The sun is
+// given as the direction its light travels, like DirectionalLight, so both can share the same values.
+use crate::evaluator::{self, Evaluator};
+use crate::light;
+use crate::shader;
+use std::f64::consts::{FRAC_PI_2, PI};
+use std::{cell::RefCell, rc::Rc};
+
+// Scales the luminance in kcd/m² to the range of the other light sources
+const LUMINANCE_SCALE: f64 = 0.05;
+// Angular radius of the sun disk in radians
+const SUN_RADIUS: f64 = 0.0047;
+// Radiance of the sun disk relative to the sun light color
+const SUN_DISK_INTENSITY: f64 = 20.0;
+// Effective wavelengths of the red, green and blue channel in micrometers
+const WAVELENGTHS: [f64; 3] = [0.68, 0.55, 0.44];
+
+fn perez(theta: f64, gamma: f64, c: [f64; 5]) -> f64 {
+    (1.0 + c[0] * (c[1] / theta.cos()).exp()) * (1.0 + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos().powi(2))
+}
+
+fn luminance_coefficients(t: f64) -> [f64; 5] {
+    [0.1787 * t - 1.4630, -0.3554 * t + 0.4275, -0.0227 * t + 5.3251, 0.1206 * t - 2.5771, -0.0670 * t + 0.3703]
+}
+
+fn x_coefficients(t: f64) -> [f64; 5] {
+    [-0.0193 * t - 0.2592, -0.0665 * t + 0.0008, -0.0004 * t + 0.2125, -0.0641 * t - 0.8989, -0.0033 * t + 0.0452]
+}
+
+fn y_coefficients(t: f64) -> [f64; 5] {
+    [-0.0167 * t - 0.2608, -0.0950 * t + 0.0092, -0.0079 * t + 0.2102, -0.0441 * t - 1.6537, -0.0109 * t + 0.0529]
+}
+
+fn zenith_chromaticity(t: f64, theta_s: f64, c: [[f64; 4]; 3]) -> f64 {
+    let th = [theta_s.powi(3), theta_s.powi(2), theta_s, 1.0];
+    let row = |r: [f64; 4]| r[0] * th[0] + r[1] * th[1] + r[2] * th[2] + r[3] * th[3];
+    t * t * row(c[0]) + t * row(c[1]) + row(c[2])
+}
+
+fn xyy_to_rgb(x: f64, y: f64, luminance: f64) -> [f64; 3] {
+    let cx = x / y * luminance;
+    let cz = (1.0 - x - y) / y * luminance;
+    [
+        (3.2406 * cx - 1.5372 * luminance - 0.4986 * cz).max(0.0),
+        (-0.9689 * cx + 1.8758 * luminance + 0.0415 * cz).max(0.0),
+        (0.0557 * cx - 0.2040 * luminance + 1.0570 * cz).max(0.0),
+    ]
+}
+
+fn normalized(d: [f64; 3]) -> [f64; 3] {
+    vecmath::vec3_normalized(d)
+}
+
+// Fades the sky out while the sun sets below the horizon, the model itself is only valid above it
+fn horizon_fade(to_sun: [f64; 3]) -> f64 {
+    let f = ((to_sun[1] + 0.1) / 0.1).clamp(0.0, 1.0);
+    f * f * (3.0 - 2.0 * f)
+}
+
+// Radiance of the sky seen in direction d, to_sun points towards the sun
+pub fn sky_radiance(d: [f64; 3], to_sun: [f64; 3], turbidity: f64) -> [f64; 3] {
+    let t = turbidity.max(1.0);
+    let theta_s = to_sun[1].clamp(-1.0, 1.0).acos().min(FRAC_PI_2);
+    // Below the horizon the sky at the horizon is continued
+    let theta = d[1].clamp(0.001, 1.0).acos();
+    let cos_gamma = vecmath::vec3_dot(d, to_sun).clamp(-1.0, 1.0);
+    let gamma = cos_gamma.acos();
+
+    let chi = (4.0 / 9.0 - t / 120.0) * (PI - 2.0 * theta_s);
+    let zenith_luminance = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.0);
+    let zenith_x = zenith_chromaticity(
+        t,
+        theta_s,
+        [
+            [0.00166, -0.00375, 0.00209, 0.0],
+            [-0.02903, 0.06377, -0.03202, 0.00394],
+            [0.11693, -0.21196, 0.06052, 0.25886],
+        ],
+    );
+    let zenith_y = zenith_chromaticity(
+        t,
+        theta_s,
+        [
+            [0.00275, -0.00610, 0.00317, 0.0],
+            [-0.04214, 0.08970, -0.04153, 0.00516],
+            [0.15346, -0.26756, 0.06670, 0.26688],
+        ],
+    );
+
+    let relative = |c: [f64; 5]| perez(theta, gamma, c) / perez(0.0, theta_s, c);
+    let luminance = zenith_luminance * relative(luminance_coefficients(t)) * LUMINANCE_SCALE;
+    let x = zenith_x * relative(x_coefficients(t));
+    let y = zenith_y * relative(y_coefficients(t));
+
+    let fade = horizon_fade(to_sun);
+    let mut rgb = xyy_to_rgb(x, y, luminance * fade);
+    if gamma < SUN_RADIUS && d[1] > 0.0 {
+        let sun = sun_color(to_sun, turbidity);
+        for c in 0..3 {
+            rgb[c] += sun[c] * SUN_DISK_INTENSITY;
+        }
+    }
+    rgb
+}
+
+// Transmittance of the atmosphere for sun light, Rayleigh and aerosol scattering along the relative air mass
+pub fn sun_color(to_sun: [f64; 3], turbidity: f64) -> [f64; 3] {
+    let theta_s = to_sun[1].clamp(-1.0, 1.0).acos().min(FRAC_PI_2);
+    let air_mass = 1.0 / (theta_s.cos() + 0.15 * (93.885 - theta_s.to_degrees()).powf(-1.253));
+    let beta = 0.04608 * turbidity.max(1.0) - 0.04586;
+    let fade = horizon_fade(to_sun);
+    let mut color = [0.0; 3];
+    for c in 0..3 {
+        let rayleigh = 0.008735 * WAVELENGTHS[c].powf(-4.08);
+        let aerosol = beta * WAVELENGTHS[c].powf(-1.3);
+        color[c] = (-air_mass * (rayleigh + aerosol)).exp() * fade;
+    }
+    color
+}
+
+pub struct PreethamSky {
+    sun: [f64!(); 3],
+    turbidity: f64!(),
+    intensity: f64!(),
+}
+
+impl PreethamSky {
+    pub fn new(sun: [f64!(); 3], turbidity: f64!(), intensity: f64!()) -> PreethamSky {
+        PreethamSky { sun, turbidity, intensity }
+    }
+
+    // A directional light that follows the sun, its color is updated by evaluators after the sun direction
+    pub fn sun_light(&self, intensity: f64!()) -> light::DirectionalLight {
+        let color = [f64!(1.0), f64!(1.0), f64!(1.0)];
+        for (c, pointer) in color.iter().enumerate() {
+            SunColorEvaluator::insert(self.sun.clone(), self.turbidity.clone(), c, pointer.clone());
+        }
+        light::DirectionalLight::new(self.sun.clone(), color, intensity)
+    }
+}
+
+impl shader::BackgroundShader for PreethamSky {
+    fn shade(&self, x: u32, y: u32, direction: [f64; 3]) -> [f64; 3] {
+        let sun = get_f64v!(self.sun);
+        let to_sun = normalized([-sun[0], -sun[1], -sun[2]]);
+        let radiance = sky_radiance(normalized(direction), to_sun, get_f64!(self.turbidity));
+        let intensity = get_f64!(self.intensity);
+        [radiance[0] * intensity, radiance[1] * intensity, radiance[2] * intensity]
+    }
+    fn evaluate(&mut self, t: f64) {}
+}
+
+// One channel of the sun light color
+pub struct SunColorEvaluator {
+    sun: [f64!(); 3],
+    turbidity: f64!(),
+    channel: usize,
+}
+
+impl Evaluator for SunColorEvaluator {
+    fn evaluate(&self, t: f64) -> f64 {
+        let sun = get_f64v!(self.sun);
+        sun_color(normalized([-sun[0], -sun[1], -sun[2]]), get_f64!(self.turbidity))[self.channel]
+    }
+}
+
+impl SunColorEvaluator {
+    pub fn insert(sun: [f64!(); 3], turbidity: f64!(), channel: usize, pointer: f64!()) {
+        let new_f = SunColorEvaluator { sun, turbidity, channel };
+        evaluator::insert_evaluator(pointer, Box::new(new_f));
+    }
+}
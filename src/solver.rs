@@ -3,17 +3,27 @@ use crate::light;
 use crate::primitive;
 use crate::configuration;
 use crate::primitive::MaterialRaw;
+use crate::texture;
 use vecmath;
 
 pub struct IntersectionInfo{
     pub hit: bool,
     pub dist: f64,
     pub steps: u32,
-    pub fractal_data: [f64;4],
+    pub fractal_data: [f64;5],
+    // Geometric normal, used for shadow rays
     pub normal: [f64;3],
+    // Normal perturbed by the bump texture, used for lighting
+    pub shading_normal: [f64;3],
     pub path_light: [f64;3],
     pub position: [f64;3],
-    pub material: MaterialRaw
+    // Direction of the ray that was solved, backgrounds are looked up with it
+    pub direction: [f64;3],
+    pub material: MaterialRaw,
+    pub overshoot: bool,
+    // Closest approach to any surface along the ray and the step it happened at, used for glow
+    pub min_dist: f64,
+    pub min_dist_step: u32
 }
 pub struct IntersectionInfoSimple{
     pub hit: bool,
@@ -28,11 +38,14 @@ pub struct SufraceProperties{
 struct Solve{
     hit: bool,
     dist: f64,
-    fractal_data: [f64;4],
+    fractal_data: [f64;5],
     steps: u32,
     pos: [f64;3],
     path_light: [f64;3],
-    material: MaterialRaw
+    material: MaterialRaw,
+    overshoot: bool,
+    min_dist: f64,
+    min_dist_step: u32
 }
 struct SolveSimple{
     hit: bool,
@@ -60,7 +73,7 @@ impl GeneralSolver{
     }
 
     fn _map_world(&self, pos: [f64;3]) -> primitive::PrimitiveResult{
-        let mut prim = primitive::PrimitiveResult { distance: f64::MAX, fractal_data: [0.0, 0.0, 0.0, 0.0], material: self.default_material };
+        let mut prim = primitive::PrimitiveResult { distance: f64::MAX, fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0], material: self.default_material };
         for p in &self.primitives{
             let dist = p.map_primitive(pos);
             if dist.distance < prim.distance{
@@ -74,20 +87,26 @@ impl GeneralSolver{
         let mut mapped_pos = [pos[0], pos[1], pos[2]];
         let mut total_dist = 0.0;
         let mut dist = self._map_world(mapped_pos);
+        let mut min_dist = f64::MAX;
+        let mut min_dist_step = 0;
         for i in 0..configuration::step_number{
             total_dist += dist.distance;
             // let new_dist = if i == 0 { dist } else {self._map_world(mapped_pos)};
             let new_dist = self._map_world(mapped_pos);
+            if new_dist.distance < min_dist {
+                min_dist = new_dist.distance;
+                min_dist_step = i;
+            }
             if new_dist.distance < configuration::min_dist {
-                return Solve{hit: true, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: [0.0, 0.0, 0.0] }
+                return Solve{hit: true, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: [0.0, 0.0, 0.0], overshoot: new_dist.distance < -configuration::min_dist, min_dist, min_dist_step }
             }
             if new_dist.distance > configuration::max_dist {
-                return Solve{hit: false, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: [0.0, 0.0, 0.0] }
+                return Solve{hit: false, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: [0.0, 0.0, 0.0], overshoot: false, min_dist, min_dist_step }
             }
             dist = new_dist;
             mapped_pos = [mapped_pos[0] + dir[0] * dist.distance, mapped_pos[1] + dir[1] * dist.distance, mapped_pos[2] + dir[2] * dist.distance]
         }
-        return Solve{hit: false, dist: total_dist, fractal_data: [0.0, 0.0, 0.0, 0.0], steps: configuration::step_number, pos: mapped_pos, material: self.default_material, path_light: [0.0, 0.0, 0.0] }
+        Solve{hit: false, dist: total_dist, fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0], steps: configuration::step_number, pos: mapped_pos, material: self.default_material, path_light: [0.0, 0.0, 0.0], overshoot: false, min_dist, min_dist_step }
     }
     
     fn _solve_world_simple(&self, pos: [f64;3], dir: [f64;3], max_dist: f64) -> SolveSimple{
@@ -114,7 +133,7 @@ impl Solver for GeneralSolver{
     fn solve(&self, ray: crate::Ray) -> IntersectionInfo{
         let res = self._solve_world(ray.1, ray.0);
         let normal = self._calculate_normal(res.pos);
-        return IntersectionInfo{dist: res.dist, hit: res.hit, fractal_data: res.fractal_data, normal: normal, position: res.pos, steps: res.steps, material: res.material, path_light: res.path_light }
+        IntersectionInfo{dist: res.dist, hit: res.hit, fractal_data: res.fractal_data, normal, shading_normal: texture::bump_normal(&res.material, normal), position: res.pos, direction: ray.0, steps: res.steps, material: texture::apply(res.material, normal), path_light: res.path_light, overshoot: res.overshoot, min_dist: res.min_dist, min_dist_step: res.min_dist_step }
     }
 
     fn solve_simple(&self, ray: crate::Ray, max_dist: f64) -> IntersectionInfoSimple{
@@ -134,7 +153,7 @@ impl GeneralSolver{
         let m = primitive::Material::new_direct();
         GeneralSolver{
             primitives,
-            default_material: primitive::MaterialRaw::new(m.albedo, m.specular, m.diffuse, m.n_specular)
+            default_material: primitive::MaterialRaw::new(&m)
         }
     }
 }
@@ -174,7 +193,7 @@ impl VolumetricSolver{
     }
 
     fn _map_world(&self, pos: [f64;3]) -> primitive::PrimitiveResult{
-        let mut prim = primitive::PrimitiveResult { distance: f64::MAX, fractal_data: [0.0, 0.0, 0.0, 0.0], material: self.default_material };
+        let mut prim = primitive::PrimitiveResult { distance: f64::MAX, fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0], material: self.default_material };
         for p in &self.primitives{
             let dist = p.map_primitive(pos);
             if dist.distance < prim.distance{
@@ -189,15 +208,21 @@ impl VolumetricSolver{
         let mut total_dist = 0.0;
         let mut dist = self._map_world(mapped_pos);
         let mut volumetric_color = [0.0, 0.0, 0.0];
+        let mut min_dist = f64::MAX;
+        let mut min_dist_step = 0;
         for i in 0..configuration::step_number{
             total_dist += dist.distance;
             // let new_dist = if i == 0 { dist } else {self._map_world(mapped_pos)};
             let new_dist = self._map_world(mapped_pos);
+            if new_dist.distance < min_dist {
+                min_dist = new_dist.distance;
+                min_dist_step = i;
+            }
             if new_dist.distance < configuration::min_dist {
-                return Solve{hit: true, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: volumetric_color }
+                return Solve{hit: true, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: volumetric_color, overshoot: new_dist.distance < -configuration::min_dist, min_dist, min_dist_step }
             }
             if new_dist.distance > configuration::max_dist {
-                return Solve{hit: false, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: volumetric_color }
+                return Solve{hit: false, dist: total_dist, fractal_data: dist.fractal_data, steps: i, pos: mapped_pos, material: dist.material, path_light: volumetric_color, overshoot: false, min_dist, min_dist_step }
             }
             dist = new_dist;
             mapped_pos = [mapped_pos[0] + dir[0] * helpers::min_f64(dist.distance, 0.05), mapped_pos[1] + dir[1] * helpers::min_f64(dist.distance, 0.05), mapped_pos[2] + dir[2] * helpers::min_f64(dist.distance, 0.05)];
@@ -211,7 +236,7 @@ impl VolumetricSolver{
                 }
             }
         }
-        return Solve{hit: false, dist: total_dist, fractal_data: [0.0, 0.0, 0.0, 0.0], steps: configuration::step_number, pos: mapped_pos, material: self.default_material, path_light: volumetric_color }
+        Solve{hit: false, dist: total_dist, fractal_data: [0.0, 0.0, 0.0, 0.0, 0.0], steps: configuration::step_number, pos: mapped_pos, material: self.default_material, path_light: volumetric_color, overshoot: false, min_dist, min_dist_step }
     }
     
     fn _solve_world_simple(&self, pos: [f64;3], dir: [f64;3], max_dist: f64) -> SolveSimple{
@@ -238,7 +263,7 @@ impl Solver for VolumetricSolver{
     fn solve(&self, ray: crate::Ray) -> IntersectionInfo{
         let res = self._solve_world(ray.1, ray.0);
         let normal = self._calculate_normal(res.pos);
-        return IntersectionInfo{dist: res.dist, hit: res.hit, fractal_data: res.fractal_data, normal: normal, position: res.pos, steps: res.steps, material: res.material, path_light: res.path_light }
+        IntersectionInfo{dist: res.dist, hit: res.hit, fractal_data: res.fractal_data, normal, shading_normal: texture::bump_normal(&res.material, normal), position: res.pos, direction: ray.0, steps: res.steps, material: texture::apply(res.material, normal), path_light: res.path_light, overshoot: res.overshoot, min_dist: res.min_dist, min_dist_step: res.min_dist_step }
     }
 
     fn solve_simple(&self, ray: crate::Ray, max_dist: f64) -> IntersectionInfoSimple{
@@ -259,7 +284,7 @@ impl VolumetricSolver{
         VolumetricSolver{
             primitives,
             lights,
-            default_material: primitive::MaterialRaw::new(m.albedo, m.specular, m.diffuse, m.n_specular)
+            default_material: primitive::MaterialRaw::new(&m)
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,209 @@
+// Textures for material channels. Every texture is a 2D pattern that is projected along the three object axes
+// and blended by the object space normal (triplanar mapping). Texture sets are kept in a per thread registry,
+// materials only store their index, so MaterialRaw stays Copy.
+use crate::configuration;
+use crate::helpers;
+use crate::noise;
+use crate::primitive::MaterialRaw;
+use std::{cell::RefCell, rc::Rc};
+
+// Higher values give sharper transitions between the three projections
+const TRIPLANAR_SHARPNESS: f64 = 4.0;
+
+thread_local! {
+    #[allow(non_upper_case_globals)]
+    static texture_sets: RefCell<Vec<TextureSet>> = const { RefCell::new(Vec::new()) };
+}
+
+pub enum Pattern {
+    // Alternates between both colors in unit cells
+    Checker,
+    // Gradient noise, Perlin or simplex
+    Noise(noise::Noise),
+    // Distance to the closest voronoi feature point
+    Voronoi(noise::Noise),
+    Image {
+        data: Vec<[f64; 3]>,
+        width: usize,
+        height: usize,
+    },
+}
+
+pub struct Texture {
+    pattern: Pattern,
+    // Procedural patterns blend from color1 to color2, images are used as they are
+    color1: [f64!(); 3],
+    color2: [f64!(); 3],
+    scale: f64!(),
+    offset: [f64!(); 3],
+}
+
+// The textured channels of one material, missing textures leave the channel untouched
+pub struct TextureSet {
+    pub albedo: Option<Texture>,
+    pub roughness: Option<Texture>,
+    pub emission: Option<Texture>,
+    // Height map for the shading normal, the red channel is used
+    pub bump: Option<Texture>,
+    pub bump_strength: f64!(),
+}
+
+pub fn load_image(path: &str) -> Pattern {
+    let img = image::open(path)
+        .expect("Something went wrong reading the texture")
+        .to_rgb32f();
+    let (width, height) = (img.width() as usize, img.height() as usize);
+    let data = img.pixels().map(|p| [p.0[0] as f64, p.0[1] as f64, p.0[2] as f64]).collect();
+    Pattern::Image { data, width, height }
+}
+
+impl Pattern {
+    // Blend factor of procedural patterns, images return None
+    fn value(&self, uv: [f64; 2]) -> Option<f64> {
+        match self {
+            Pattern::Checker => {
+                let cell = uv[0].floor() as i64 + uv[1].floor() as i64;
+                Some(if cell.rem_euclid(2) == 0 { 0.0 } else { 1.0 })
+            }
+            Pattern::Noise(n) => Some((n.sample([uv[0], uv[1], 0.0]) * 0.5 + 0.5).clamp(0.0, 1.0)),
+            Pattern::Voronoi(n) => Some(n.sample([uv[0], uv[1], 0.0]).clamp(0.0, 1.0)),
+            Pattern::Image { .. } => None,
+        }
+    }
+
+    // Nearest texel, the image repeats once per unit
+    fn texel(&self, uv: [f64; 2]) -> [f64; 3] {
+        match self {
+            Pattern::Image { data, width, height } => {
+                let u = uv[0] - uv[0].floor();
+                let v = uv[1] - uv[1].floor();
+                let x = ((u * *width as f64) as usize).min(width - 1);
+                // Images are stored top down
+                let y = (((1.0 - v) * *height as f64) as usize).min(height - 1);
+                data[y * width + x]
+            }
+            _ => [0.0, 0.0, 0.0],
+        }
+    }
+}
+
+impl Texture {
+    pub fn new(pattern: Pattern, color1: [f64!(); 3], color2: [f64!(); 3], scale: f64!(), offset: [f64!(); 3]) -> Texture {
+        Texture { pattern, color1, color2, scale, offset }
+    }
+
+    fn sample_2d(&self, uv: [f64; 2]) -> [f64; 3] {
+        match self.pattern.value(uv) {
+            Some(f) => helpers::vec_interpolate(get_f64v!(self.color1), get_f64v!(self.color2), f),
+            None => self.pattern.texel(uv),
+        }
+    }
+
+    // pos and normal are in object space
+    pub fn sample(&self, pos: [f64; 3], normal: [f64; 3]) -> [f64; 3] {
+        let scale = get_f64!(self.scale);
+        let offset = get_f64v!(self.offset);
+        let p = [
+            pos[0] * scale + offset[0],
+            pos[1] * scale + offset[1],
+            pos[2] * scale + offset[2],
+        ];
+
+        let mut w = [
+            normal[0].abs().powf(TRIPLANAR_SHARPNESS),
+            normal[1].abs().powf(TRIPLANAR_SHARPNESS),
+            normal[2].abs().powf(TRIPLANAR_SHARPNESS),
+        ];
+        let sum = (w[0] + w[1] + w[2]).max(1e-9);
+        w = [w[0] / sum, w[1] / sum, w[2] / sum];
+
+        let x = self.sample_2d([p[2], p[1]]);
+        let y = self.sample_2d([p[0], p[2]]);
+        let z = self.sample_2d([p[0], p[1]]);
+        [
+            x[0] * w[0] + y[0] * w[1] + z[0] * w[2],
+            x[1] * w[0] + y[1] * w[1] + z[1] * w[2],
+            x[2] * w[0] + y[2] * w[1] + z[2] * w[2],
+        ]
+    }
+}
+
+pub fn register(set: TextureSet) -> usize {
+    texture_sets.with(|sets| {
+        let mut sets = sets.borrow_mut();
+        sets.push(set);
+        sets.len() - 1
+    })
+}
+
+// Resolves the textured channels of a material at a surface point, normal is in world space and is brought into
+// object space by the normal frame of the texture space
+pub fn apply(material: MaterialRaw, normal: [f64; 3]) -> MaterialRaw {
+    let (index, space) = match (material.texture, material.texture_space) {
+        (Some(index), Some(space)) => (index, space),
+        _ => return material,
+    };
+    let local_normal = vecmath::vec3_normalized(helpers::mat_dir_mul3(space.frame, normal));
+
+    texture_sets.with(|sets| {
+        let sets = sets.borrow();
+        let set = &sets[index];
+        let mut m = material;
+        if let Some(t) = &set.albedo {
+            let c = t.sample(space.pos, local_normal);
+            m.color = [m.color[0] * c[0], m.color[1] * c[1], m.color[2] * c[2]];
+        }
+        if let Some(t) = &set.roughness {
+            // Grayscale, the red channel is used
+            m.roughness *= t.sample(space.pos, local_normal)[0];
+        }
+        if let Some(t) = &set.emission {
+            let c = t.sample(space.pos, local_normal);
+            m.emission = [m.emission[0] * c[0], m.emission[1] * c[1], m.emission[2] * c[2]];
+        }
+        m
+    })
+}
+
+// Tilts the shading normal along the slope of the bump texture, normal is the geometric normal in world space
+pub fn bump_normal(material: &MaterialRaw, normal: [f64; 3]) -> [f64; 3] {
+    let (index, space) = match (material.texture, material.texture_space) {
+        (Some(index), Some(space)) => (index, space),
+        _ => return normal,
+    };
+
+    texture_sets.with(|sets| {
+        let sets = sets.borrow();
+        let set = &sets[index];
+        let bump = match &set.bump {
+            Some(b) => b,
+            None => return normal,
+        };
+        let local_normal = vecmath::vec3_normalized(helpers::mat_dir_mul3(space.frame, normal));
+        let height = |p: [f64; 3]| bump.sample(p, local_normal)[0];
+
+        let e = configuration::small_step;
+        let p = space.pos;
+        let gradient = [
+            (height([p[0] + e, p[1], p[2]]) - height([p[0] - e, p[1], p[2]])) / (2.0 * e),
+            (height([p[0], p[1] + e, p[2]]) - height([p[0], p[1] - e, p[2]])) / (2.0 * e),
+            (height([p[0], p[1], p[2] + e]) - height([p[0], p[1], p[2] - e])) / (2.0 * e),
+        ];
+        // Only the slope along the surface tilts the normal
+        let along = vecmath::vec3_dot(gradient, local_normal);
+        let strength = get_f64!(set.bump_strength);
+        let tilted = [
+            local_normal[0] - strength * (gradient[0] - along * local_normal[0]),
+            local_normal[1] - strength * (gradient[1] - along * local_normal[1]),
+            local_normal[2] - strength * (gradient[2] - along * local_normal[2]),
+        ];
+
+        // The frame maps world normals into object space, so its inverse maps the tilted normal back
+        let det = vecmath::mat3_det(space.frame);
+        if !det.is_finite() || det.abs() < 1e-12 {
+            return normal;
+        }
+        let to_world = vecmath::mat3_inv(space.frame);
+        vecmath::vec3_normalized(helpers::mat_dir_mul3(to_world, tilted))
+    })
+}
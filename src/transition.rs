@@ -1,3 +1,5 @@
+use crate::helpers;
+
 pub trait Transition{
     fn transition(&self, t: f64) -> f64;
 }
@@ -57,7 +59,7 @@ pub struct CatmullRomSpline {
 }
 impl Transition for CatmullRomSpline{
     fn transition(&self, t: f64) -> f64{
-        return self.catmull_rom_spline(t, self.p0, 0.0, 1.0, self.p3);
+        helpers::catmull_rom([[self.p0], [0.0], [1.0], [self.p3]], t)[0]
     }
 
 }
@@ -68,7 +70,4 @@ impl CatmullRomSpline{
             p0, p3
         }
     }
-    fn catmull_rom_spline(&self, t: f64, p0: f64, p1: f64, p2: f64, p3: f64) -> f64{
-        return 0.5 * ((2.0 * p1) + (-p0 + p2) * t + (2.0 * p0 - 5.0 * p1 + 4.0 * p2 - p3) * t * t + (-p0 + 3.0 * p1 - 3.0 * p2 + p3) * t * t * t)
-    }
 }